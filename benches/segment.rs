@@ -0,0 +1,62 @@
+//! Edits of large documents with many authorship runs, in both buffer storages
+use adopted_rs::segment::{tree::SegmentTree, Flat, Segment, SegmentBuffer, Storage};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Lengths of documents, in bytes
+const SIZES: [usize; 3] = [64 * 1024, 1024 * 1024, 5 * 1024 * 1024];
+/// Length of single authorship run
+const RUN: usize = 1000;
+
+fn document<S: Storage>(size: usize) -> SegmentBuffer<S> {
+    SegmentBuffer::from_segments(
+        (0..size / RUN).map(|i| Segment::new(i as u16 % 8, &[b'a' + (i % 26) as u8; RUN])),
+    )
+}
+
+/// Deterministic positions spread over document
+fn positions(len: usize) -> impl FnMut() -> usize {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % len as u64) as usize
+    }
+}
+
+fn bench_storage<S: Storage>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group("splice");
+    for size in SIZES.iter().copied() {
+        let mut buf = document::<S>(size);
+        let mut next = positions(buf.len() - 1);
+        group.bench_function(BenchmarkId::new(name, size), |b| {
+            b.iter(|| {
+                let pos = next();
+                let insert = SegmentBuffer::from_segments(Some(Segment::new(9, b"x")));
+                buf.splice(pos..pos + 1, Some(insert));
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("slice");
+    for size in SIZES.iter().copied() {
+        let buf = document::<S>(size);
+        let mut next = positions(buf.len() - 4096);
+        group.bench_function(BenchmarkId::new(name, size), |b| {
+            b.iter(|| {
+                let pos = next();
+                buf.slice(pos..pos + 4096)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn storages(c: &mut Criterion) {
+    bench_storage::<Flat>(c, "flat");
+    bench_storage::<SegmentTree>(c, "tree");
+}
+
+criterion_group!(benches, storages);
+criterion_main!(benches);
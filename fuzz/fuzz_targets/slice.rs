@@ -0,0 +1,25 @@
+//! Random slices of buffer, built from arbitrary segments
+#![no_main]
+use adopted_rs::segment::{
+    model::{run, Action},
+    tree::SegmentTree,
+    Flat,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (Vec<(u16, Vec<u8>)>, Vec<(u8, u8)>)| {
+    let (initial, ranges) = input;
+    let actions = ranges
+        .into_iter()
+        .map(|(start, end)| Action::Slice {
+            start: start as usize,
+            end: end as usize,
+        })
+        .collect::<Vec<_>>();
+    if let Err(e) = run::<Flat>(&initial, &actions) {
+        panic!("flat: {}", e);
+    }
+    if let Err(e) = run::<SegmentTree>(&initial, &actions) {
+        panic!("tree: {}", e);
+    }
+});
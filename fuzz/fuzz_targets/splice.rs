@@ -0,0 +1,51 @@
+//! Random splice, slice and compact sequences against byte-vector model
+#![no_main]
+use adopted_rs::segment::{
+    model::{run, Action},
+    tree::SegmentTree,
+    Flat,
+};
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Input {
+    Splice {
+        start: u8,
+        end: u8,
+        insert: Vec<(u16, Vec<u8>)>,
+    },
+    Slice {
+        start: u8,
+        end: u8,
+    },
+    Compact,
+}
+
+impl From<Input> for Action {
+    fn from(input: Input) -> Self {
+        match input {
+            Input::Splice { start, end, insert } => Action::Splice {
+                start: start as usize,
+                end: end as usize,
+                insert,
+            },
+            Input::Slice { start, end } => Action::Slice {
+                start: start as usize,
+                end: end as usize,
+            },
+            Input::Compact => Action::Compact,
+        }
+    }
+}
+
+fuzz_target!(|input: (Vec<(u16, Vec<u8>)>, Vec<Input>)| {
+    let (initial, inputs) = input;
+    let actions = inputs.into_iter().map(Action::from).collect::<Vec<_>>();
+    if let Err(e) = run::<Flat>(&initial, &actions) {
+        panic!("flat: {}", e);
+    }
+    if let Err(e) = run::<SegmentTree>(&initial, &actions) {
+        panic!("tree: {}", e);
+    }
+});
@@ -0,0 +1,444 @@
+//! Compact binary encoding of requests
+//!
+//! Integers are encoded as LEB128 varints, state vectors only contain non-zero
+//! components, segment data is length-prefixed.
+use std::fmt;
+
+use crate::{
+    op::{Delete, Insert, Operation, Split},
+    recon::Recon,
+    request::{dor::DoRequest, redo::RedoRequest, undo::UndoRequest, Request},
+    segment::{Segment, SegmentBuffer},
+    vector::StateVector,
+    SessionId,
+};
+
+/// Splits nested deeper than that are rejected, protects decoder from stack overflow
+const MAX_DEPTH: usize = 64;
+
+const REQUEST_DO: u8 = 0;
+const REQUEST_UNDO: u8 = 1;
+const REQUEST_REDO: u8 = 2;
+
+const OP_NOOP: u8 = 0;
+const OP_INSERT: u8 = 1;
+const OP_DELETE_REVERSIBLE: u8 = 2;
+const OP_DELETE: u8 = 3;
+const OP_SPLIT: u8 = 4;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DecodeError {
+    UnexpectedEof,
+    VarintOverflow,
+    InvalidTag(u8),
+    SessionOutOfRange(u64),
+    TooDeep,
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of input"),
+            DecodeError::VarintOverflow => write!(f, "varint is too large"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag: {}", tag),
+            DecodeError::SessionOutOfRange(id) => write!(f, "session id out of range: {}", id),
+            DecodeError::TooDeep => write!(f, "operation nesting is too deep"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} trailing bytes after value", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.0.push(byte);
+                break;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.varint(value as u64)
+    }
+
+    fn vector(&mut self, vector: &StateVector) {
+        self.usize(vector.iter().count());
+        for (user, value) in vector.iter() {
+            self.varint(user as u64);
+            self.usize(*value);
+        }
+    }
+
+    fn buffer(&mut self, buffer: &SegmentBuffer) {
+        self.usize(buffer.segments().count());
+        for segment in buffer.segments() {
+            self.varint(segment.user() as u64);
+            self.usize(segment.len());
+            self.0.extend_from_slice(&segment);
+        }
+    }
+
+    fn recon(&mut self, recon: &Recon) {
+        self.usize(recon.len());
+        for segment in recon.iter() {
+            self.usize(segment.offset);
+            self.buffer(&segment.buffer);
+        }
+    }
+
+    fn operation(&mut self, operation: &Operation) {
+        match operation {
+            Operation::NoOp => self.0.push(OP_NOOP),
+            Operation::Insert(insert) => {
+                self.0.push(OP_INSERT);
+                self.usize(insert.position);
+                self.buffer(insert.buffer());
+            }
+            Operation::Delete(delete) => {
+                match delete.buf() {
+                    Some(buf) => {
+                        self.0.push(OP_DELETE_REVERSIBLE);
+                        self.usize(delete.position);
+                        self.buffer(buf);
+                    }
+                    None => {
+                        self.0.push(OP_DELETE);
+                        self.usize(delete.position);
+                        self.usize(delete.len());
+                    }
+                }
+                self.recon(&delete.recon);
+            }
+            Operation::Split(split) => {
+                self.0.push(OP_SPLIT);
+                self.operation(&split.0);
+                self.operation(&split.1);
+            }
+        }
+    }
+
+    fn request(&mut self, request: &Request) {
+        let tag = match request {
+            Request::Do(_) => REQUEST_DO,
+            Request::Undo(_) => REQUEST_UNDO,
+            Request::Redo(_) => REQUEST_REDO,
+        };
+        self.0.push(tag);
+        self.varint(request.user() as u64);
+        self.vector(request.vector());
+        if let Request::Do(dor) = request {
+            self.operation(dor.operation());
+        }
+    }
+}
+
+struct Reader<'i>(&'i [u8]);
+
+impl<'i> Reader<'i> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let (byte, rest) = self.0.split_first().ok_or(DecodeError::UnexpectedEof)?;
+        self.0 = rest;
+        Ok(*byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'i [u8], DecodeError> {
+        if self.0.len() < len {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let part = (byte & 0x7f) as u64;
+            if shift == 63 && part > 1 {
+                return Err(DecodeError::VarintOverflow);
+            }
+            value |= part << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::VarintOverflow)
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        let value = self.varint()?;
+        if value > usize::MAX as u64 {
+            return Err(DecodeError::VarintOverflow);
+        }
+        Ok(value as usize)
+    }
+
+    fn session(&mut self) -> Result<SessionId, DecodeError> {
+        let value = self.varint()?;
+        if value > SessionId::MAX as u64 {
+            return Err(DecodeError::SessionOutOfRange(value));
+        }
+        Ok(value as SessionId)
+    }
+
+    fn vector(&mut self) -> Result<StateVector, DecodeError> {
+        let mut vector = StateVector::new();
+        for _ in 0..self.usize()? {
+            let user = self.session()?;
+            let value = self.usize()?;
+            vector.set(user, value);
+        }
+        Ok(vector)
+    }
+
+    fn buffer(&mut self) -> Result<SegmentBuffer, DecodeError> {
+        let count = self.usize()?;
+        // Every segment takes at least two bytes, do not trust count for preallocation
+        let mut segments = smallvec::SmallVec::with_capacity(count.min(self.0.len() / 2));
+        for _ in 0..count {
+            let user = self.session()?;
+            let len = self.usize()?;
+            let data = self.bytes(len)?;
+            if !data.is_empty() {
+                segments.push(Segment::new(user, data));
+            }
+        }
+        Ok(SegmentBuffer::new(segments))
+    }
+
+    fn recon(&mut self) -> Result<Recon, DecodeError> {
+        let mut recon = Recon::new();
+        for _ in 0..self.usize()? {
+            let offset = self.usize()?;
+            let buffer = self.buffer()?;
+            recon.add(offset, buffer);
+        }
+        Ok(recon)
+    }
+
+    fn operation(&mut self, depth: usize) -> Result<Operation, DecodeError> {
+        if depth > MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        Ok(match self.byte()? {
+            OP_NOOP => Operation::NoOp,
+            OP_INSERT => {
+                let position = self.usize()?;
+                Insert::new(position, self.buffer()?).into()
+            }
+            OP_DELETE_REVERSIBLE => {
+                let position = self.usize()?;
+                let buffer = self.buffer()?;
+                Delete::new(position, Ok(buffer), self.recon()?).into()
+            }
+            OP_DELETE => {
+                let position = self.usize()?;
+                let len = self.usize()?;
+                Delete::new(position, Err(len), self.recon()?).into()
+            }
+            OP_SPLIT => {
+                let first = self.operation(depth + 1)?;
+                let second = self.operation(depth + 1)?;
+                Split::new(first, second).into()
+            }
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        })
+    }
+
+    fn request(&mut self) -> Result<Request, DecodeError> {
+        let tag = self.byte()?;
+        let user = self.session()?;
+        let vector = self.vector()?;
+        Ok(match tag {
+            REQUEST_DO => Request::Do(DoRequest::new(user, vector, self.operation(0)?)),
+            REQUEST_UNDO => Request::Undo(UndoRequest { user, vector }),
+            REQUEST_REDO => Request::Redo(RedoRequest { user, vector }),
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        })
+    }
+
+    fn finish<T>(self, value: T) -> Result<T, DecodeError> {
+        if !self.0.is_empty() {
+            return Err(DecodeError::TrailingBytes(self.0.len()));
+        }
+        Ok(value)
+    }
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer(Vec::new());
+        writer.request(self);
+        writer.0
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader(input);
+        let request = reader.request()?;
+        reader.finish(request)
+    }
+}
+
+impl StateVector {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer(Vec::new());
+        writer.vector(self);
+        writer.0
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader(input);
+        let vector = reader.vector()?;
+        reader.finish(vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DecodeError;
+    use crate::{
+        op::{Delete, Insert, Operation, Split},
+        recon::Recon,
+        request::{dor::DoRequest, redo::RedoRequest, Request},
+        segment::{Segment, SegmentBuffer},
+        vector::StateVector,
+    };
+    use smallvec::smallvec;
+
+    fn vector() -> StateVector {
+        let mut vector = StateVector::new();
+        vector.set(1, 300);
+        vector.set(60000, 1);
+        vector
+    }
+
+    fn round_trip(request: Request) -> Request {
+        let encoded = request.encode();
+        let decoded = Request::decode(&encoded).unwrap();
+        assert_eq!(decoded.encode(), encoded);
+        decoded
+    }
+
+    #[test]
+    fn sparse_vector() {
+        let mut vector = vector();
+        vector.set(5, 0);
+        let encoded = vector.encode();
+        // count, 1 => 300, 60000 => 1
+        assert_eq!(encoded, [2, 1, 0xac, 0x02, 0xe0, 0xd4, 0x03, 1]);
+        assert_eq!(StateVector::decode(&encoded).unwrap(), vector);
+    }
+
+    #[test]
+    fn insert() {
+        let decoded = round_trip(Request::Do(DoRequest::new(
+            2,
+            vector(),
+            Insert::new(7, SegmentBuffer::from_bytes(2, b"hello")).into(),
+        )));
+        assert_eq!(decoded.user(), 2);
+        assert_eq!(decoded.vector(), &vector());
+        match decoded.operation() {
+            Some(Operation::Insert(insert)) => {
+                assert_eq!(insert.position, 7);
+                assert_eq!(insert.buffer(), &SegmentBuffer::from_bytes(2, b"hello"));
+            }
+            _ => panic!("expected insert"),
+        }
+    }
+
+    #[test]
+    fn delete() {
+        let mut recon = Recon::new();
+        recon.add(1, SegmentBuffer::from_bytes(3, b"x"));
+        let operation = Split::new(
+            Delete::new(
+                1,
+                Ok(SegmentBuffer::new(smallvec![
+                    Segment::new(1, b"a"),
+                    Segment::new(2, b"b"),
+                ])),
+                Recon::new(),
+            ),
+            Delete::new(5, Err(3), recon),
+        );
+        let decoded = round_trip(Request::Do(DoRequest::new(1, vector(), operation.into())));
+        match decoded.operation() {
+            Some(Operation::Split(split)) => match (&split.0, &split.1) {
+                (Operation::Delete(first), Operation::Delete(second)) => {
+                    assert_eq!(first.len(), 2);
+                    assert!(first.buf().is_some());
+                    assert_eq!(second.len(), 3);
+                    assert!(second.buf().is_none());
+                    assert_eq!(second.recon.len(), 1);
+                }
+                _ => panic!("expected deletes"),
+            },
+            _ => panic!("expected split"),
+        }
+    }
+
+    #[test]
+    fn redo() {
+        let decoded = round_trip(Request::Redo(RedoRequest {
+            user: 4,
+            vector: vector(),
+        }));
+        assert!(matches!(decoded, Request::Redo(_)));
+    }
+
+    #[test]
+    fn malformed() {
+        let encoded = Request::Do(DoRequest::new(
+            2,
+            vector(),
+            Insert::new(7, SegmentBuffer::from_bytes(2, b"hello")).into(),
+        ))
+        .encode();
+        for len in 0..encoded.len() {
+            assert!(matches!(
+                Request::decode(&encoded[..len]),
+                Err(DecodeError::UnexpectedEof)
+            ));
+        }
+
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(matches!(
+            Request::decode(&trailing),
+            Err(DecodeError::TrailingBytes(1))
+        ));
+
+        assert!(matches!(
+            Request::decode(&[7, 1, 0]),
+            Err(DecodeError::InvalidTag(7))
+        ));
+        assert!(matches!(
+            Request::decode(&[1, 0x80, 0x80, 0x04, 0]),
+            Err(DecodeError::SessionOutOfRange(65536))
+        ));
+        assert!(matches!(
+            StateVector::decode(&[
+                1, 1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f
+            ]),
+            Err(DecodeError::VarintOverflow)
+        ));
+
+        let mut nested = vec![0, 1, 0];
+        nested.extend(std::iter::repeat_n(4, 100));
+        assert!(matches!(
+            Request::decode(&nested),
+            Err(DecodeError::TooDeep)
+        ));
+    }
+}
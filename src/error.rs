@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::{SessionId, TextPosition, TextSize};
+
+/// Error caused by invalid or inconsistent requests and operations
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OtError {
+    /// Operation touches text past the end of buffer
+    OutOfRange { end: TextPosition, len: TextSize },
+    /// Deleted text is required, but operation only knows its length
+    NonReversible,
+    /// Reversible and non-reversible deletes can't be merged
+    MixedReversibility,
+    /// Request count of session can't go below zero
+    VectorUnderflow(SessionId),
+    /// Request count of session can't go past `usize::MAX`
+    VectorOverflow(SessionId),
+    /// Undo/redo request has nothing to revert
+    NoAssociatedRequest,
+    /// Request can't be translated to the target state with known requests
+    NoTranslationPath,
+    /// Request depends on requests, which were not executed yet
+    NotReady,
+    /// Request is already executed
+    Duplicate,
+    /// Concurrent inserts at the same position can't be ordered without concurrency id
+    AmbiguousOrder,
+    /// Undo/redo request is mirrored or transformed before it is translated to do request
+    NotTranslated,
+    /// Deleted text is taken from operation, which also inserts text
+    UnexpectedInsert,
+    /// Text carried by reversible delete differs from the text, it is applied to
+    DeletedTextMismatch,
+    /// Snapshot log doesn't match its state vector
+    InvalidSnapshot,
+    /// Session never joined the document
+    UnknownSession(SessionId),
+    /// Session left the document
+    DepartedSession(SessionId),
+    /// Session id is already used
+    SessionExists(SessionId),
+    /// Every session id is already used
+    SessionsExhausted,
+    /// Session ids are only allocated by authority, see [`State::with_authority`]
+    ///
+    /// [`State::with_authority`]: crate::State::with_authority
+    NotAuthority,
+    /// Position points inside of multi-unit code point
+    InsideCodePoint(TextPosition),
+    /// Text is not valid UTF-8, so its units can't be counted
+    InvalidUtf8,
+    /// Position unit name is not recognized
+    UnknownUnit(String),
+}
+
+impl fmt::Display for OtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OtError::OutOfRange { end, len } => {
+                write!(f, "range end {} is out of buffer of length {}", end, len)
+            }
+            OtError::NonReversible => write!(f, "operation requires deleted text"),
+            OtError::MixedReversibility => {
+                write!(f, "cannot merge reversible operation with non-reversible")
+            }
+            OtError::VectorUnderflow(session) => {
+                write!(f, "request count of session {} underflow", session)
+            }
+            OtError::VectorOverflow(session) => {
+                write!(f, "request count of session {} overflow", session)
+            }
+            OtError::NoAssociatedRequest => write!(f, "nothing to undo or redo"),
+            OtError::NoTranslationPath => write!(f, "no translation path found"),
+            OtError::NotReady => write!(f, "request depends on not yet executed requests"),
+            OtError::Duplicate => write!(f, "request is already executed"),
+            OtError::AmbiguousOrder => write!(f, "concurrent inserts need concurrency id"),
+            OtError::NotTranslated => write!(f, "undo/redo request should be translated first"),
+            OtError::UnexpectedInsert => {
+                write!(f, "operation inserts text, where delete is expected")
+            }
+            OtError::DeletedTextMismatch => {
+                write!(f, "deleted text doesn't match text in buffer")
+            }
+            OtError::InvalidSnapshot => write!(f, "snapshot log doesn't match its state"),
+            OtError::UnknownSession(session) => write!(f, "unknown session {}", session),
+            OtError::DepartedSession(session) => write!(f, "session {} left", session),
+            OtError::SessionExists(session) => write!(f, "session {} already exists", session),
+            OtError::SessionsExhausted => write!(f, "no free session ids left"),
+            OtError::NotAuthority => write!(f, "session ids are allocated by authority"),
+            OtError::InsideCodePoint(pos) => write!(f, "position {} is inside of code point", pos),
+            OtError::InvalidUtf8 => write!(f, "text is not valid utf-8"),
+            OtError::UnknownUnit(unit) => write!(f, "unknown position unit {:?}", unit),
+        }
+    }
+}
+
+impl std::error::Error for OtError {}
@@ -0,0 +1,762 @@
+//! Infinote (libinfinity/Gobby) XML request format
+//!
+//! ```xml
+//! <request user="1" time=""><insert pos="3">text</insert></request>
+//! <request user="1" time="2:1"><delete pos="3" len="2"/></request>
+//! <request user="2" time="1:2"><undo/></request>
+//! ```
+//!
+//! Libinfinity sends `time` as a difference to the state of user after its previous
+//! request, see [`from_xml_diff`] and [`to_xml_diff`], full state vectors are only
+//! used while synchronizing session. Text is authored by user of request, unless it is
+//! split into `<segment author="…">` elements. Positions are counted in characters,
+//! so states exchanging requests with libinfinity should use [`Unit::Chars`]. Caret
+//! variants (`insert-caret`, `delete-caret`, `undo-caret`, `redo-caret`) are accepted
+//! and mapped onto plain operations, as this crate doesn't track carets.
+use std::{fmt, str::FromStr};
+
+use crate::{
+    op::{Delete, Insert, Operation, Split},
+    recon::Recon,
+    request::{dor::DoRequest, redo::RedoRequest, undo::UndoRequest, Request},
+    segment::{Segment, SegmentBuffer},
+    unit::Unit,
+    vector::StateVector,
+    SessionId,
+};
+
+/// Elements nested deeper than that are rejected, protects parser from stack overflow
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum XmlError {
+    /// Input is not well-formed xml
+    Syntax(usize, &'static str),
+    UnexpectedElement(String),
+    MissingAttribute(&'static str),
+    InvalidAttribute(&'static str, String),
+    /// Request should contain exactly one operation
+    InvalidOperationCount(usize),
+    /// Text of reversible delete doesn't match its `len`
+    LengthMismatch,
+    /// Segment text is not valid utf-8, and can't be written
+    InvalidText,
+    /// Elements are nested too deep
+    TooDeep,
+    /// Request isn't issued after previous request of its user, so its time can't be
+    /// written as a difference
+    TimeBeforePrevious,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlError::Syntax(offset, msg) => write!(f, "syntax error at {}: {}", offset, msg),
+            XmlError::UnexpectedElement(name) => write!(f, "unexpected element <{}>", name),
+            XmlError::MissingAttribute(name) => write!(f, "missing attribute {}", name),
+            XmlError::InvalidAttribute(name, value) => {
+                write!(f, "invalid attribute {}=\"{}\"", name, value)
+            }
+            XmlError::InvalidOperationCount(n) => {
+                write!(f, "request should contain single operation, got {}", n)
+            }
+            XmlError::LengthMismatch => write!(f, "delete text doesn't match its length"),
+            XmlError::InvalidText => write!(f, "segment text is not valid utf-8"),
+            XmlError::TooDeep => write!(f, "elements are nested too deep"),
+            XmlError::TimeBeforePrevious => {
+                write!(f, "request time is before previous request of its user")
+            }
+        }
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+/// Parses single `<request>` element, which `time` is a full state vector
+pub fn from_xml(xml: &str) -> Result<Request, XmlError> {
+    request(&parse(xml)?, |_, time| Ok(time))
+}
+
+/// Parses single `<request>` element as sent by libinfinity, which `time` is a
+/// difference to `previous` state of its user
+///
+/// State of user is its vector after its previous request, i.e. vector of that request
+/// with its own component incremented, and is empty before the first one
+pub fn from_xml_diff(
+    xml: &str,
+    previous: impl FnOnce(SessionId) -> StateVector,
+) -> Result<Request, XmlError> {
+    request(&parse(xml)?, |user, diff| {
+        let mut time = previous(user);
+        for (session, n) in diff.iter() {
+            time.checked_add(session, *n)
+                .ok_or_else(|| XmlError::InvalidAttribute("time", diff.to_string()))?;
+        }
+        Ok(time)
+    })
+}
+
+fn parse(xml: &str) -> Result<Element, XmlError> {
+    let mut parser = Parser { input: xml, pos: 0 };
+    parser.skip_prolog()?;
+    let element = parser.element(0)?;
+    parser.skip_misc()?;
+    if parser.pos != xml.len() {
+        return Err(parser.error("trailing data after root element"));
+    }
+    Ok(element)
+}
+
+/// Writes request as `<request>` element with full state vector as `time`
+pub fn to_xml(request: &Request) -> Result<String, XmlError> {
+    write_request(request, request.vector())
+}
+
+/// Writes request as `<request>` element with `time` relative to `previous` state of
+/// its user, see [`from_xml_diff`]
+pub fn to_xml_diff(request: &Request, previous: &StateVector) -> Result<String, XmlError> {
+    if !previous.casually_before(request.vector()) {
+        return Err(XmlError::TimeBeforePrevious);
+    }
+    write_request(request, &previous.diff(request.vector()))
+}
+
+fn write_request(request: &Request, time: &StateVector) -> Result<String, XmlError> {
+    let mut out = String::new();
+    out.push_str("<request user=\"");
+    out.push_str(&request.user().to_string());
+    out.push_str("\" time=\"");
+    out.push_str(&time.to_string());
+    out.push_str("\">");
+    match request {
+        Request::Do(dor) => write_operation(&mut out, dor.operation(), request.user())?,
+        Request::Undo(_) => out.push_str("<undo/>"),
+        Request::Redo(_) => out.push_str("<redo/>"),
+    }
+    out.push_str("</request>");
+    Ok(out)
+}
+
+/// Inserted text authored by someone else than `user` is written as `<segment>` elements
+fn write_operation(
+    out: &mut String,
+    operation: &Operation,
+    user: SessionId,
+) -> Result<(), XmlError> {
+    match operation {
+        Operation::NoOp => out.push_str("<no-op/>"),
+        Operation::Insert(insert) => {
+            out.push_str(&format!("<insert pos=\"{}\">", insert.position));
+            let buffer = insert.buffer();
+            if buffer.chunks().all(|segment| segment.user() == user) {
+                for segment in buffer.segments() {
+                    escape(out, text(&segment)?);
+                }
+            } else {
+                write_segments(out, buffer)?;
+            }
+            out.push_str("</insert>");
+        }
+        Operation::Delete(delete) => match delete.buf() {
+            Some(buf) => {
+                out.push_str(&format!("<delete pos=\"{}\">", delete.position));
+                write_segments(out, buf)?;
+                out.push_str("</delete>");
+            }
+            None => out.push_str(&format!(
+                "<delete pos=\"{}\" len=\"{}\"/>",
+                delete.position,
+                delete.len()
+            )),
+        },
+        Operation::Split(split) => {
+            out.push_str("<split>");
+            write_operation(out, &split.0, user)?;
+            write_operation(out, &split.1, user)?;
+            out.push_str("</split>");
+        }
+    }
+    Ok(())
+}
+
+fn write_segments(out: &mut String, buffer: &SegmentBuffer) -> Result<(), XmlError> {
+    for segment in buffer.segments() {
+        out.push_str(&format!("<segment author=\"{}\">", segment.user()));
+        escape(out, text(&segment)?);
+        out.push_str("</segment>");
+    }
+    Ok(())
+}
+
+fn text(segment: &Segment) -> Result<&str, XmlError> {
+    std::str::from_utf8(segment).map_err(|_| XmlError::InvalidText)
+}
+
+fn escape(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// `time` maps user and `time` attribute onto vector of request
+fn request(
+    element: &Element,
+    time: impl FnOnce(SessionId, StateVector) -> Result<StateVector, XmlError>,
+) -> Result<Request, XmlError> {
+    if element.name != "request" {
+        return Err(XmlError::UnexpectedElement(element.name.clone()));
+    }
+    let user = element.parse_attribute::<SessionId>("user")?;
+    let vector = time(user, element.parse_attribute::<StateVector>("time")?)?;
+
+    let children = element.elements().collect::<Vec<_>>();
+    if children.len() != 1 {
+        return Err(XmlError::InvalidOperationCount(children.len()));
+    }
+    Ok(match children[0].name.as_str() {
+        "undo" | "undo-caret" => Request::Undo(UndoRequest { user, vector }),
+        "redo" | "redo-caret" => Request::Redo(RedoRequest { user, vector }),
+        _ => Request::Do(DoRequest::new(user, vector, operation(children[0], user)?)),
+    })
+}
+
+fn operation(element: &Element, user: SessionId) -> Result<Operation, XmlError> {
+    Ok(match element.name.as_str() {
+        "no-op" => Operation::NoOp,
+        "insert" | "insert-caret" => {
+            let pos = element.parse_attribute("pos")?;
+            let buffer = match element.elements().next() {
+                Some(_) => segments(element)?,
+                None => SegmentBuffer::from_text(user, &element.text(), Unit::Chars),
+            };
+            Insert::new(pos, buffer).into()
+        }
+        "delete" | "delete-caret" => {
+            let pos = element.parse_attribute("pos")?;
+            let buf = segments(element)?;
+
+            match element.attributes.iter().find(|(n, _)| n == "len") {
+                Some(_) if buf.is_empty() => {
+                    Delete::new(pos, Err(element.parse_attribute("len")?), Recon::new()).into()
+                }
+                Some(_) if element.parse_attribute::<usize>("len")? != buf.len() => {
+                    return Err(XmlError::LengthMismatch)
+                }
+                _ => Delete::reversible(pos, buf, Recon::new()).into(),
+            }
+        }
+        "split" => {
+            let children = element.elements().collect::<Vec<_>>();
+            if children.len() != 2 {
+                return Err(XmlError::InvalidOperationCount(children.len()));
+            }
+            Split::new(operation(children[0], user)?, operation(children[1], user)?).into()
+        }
+        name => return Err(XmlError::UnexpectedElement(name.to_owned())),
+    })
+}
+
+/// Text of `<segment>` children, text outside of them is ignored
+fn segments(element: &Element) -> Result<SegmentBuffer, XmlError> {
+    let segments = element
+        .elements()
+        .map(|segment| {
+            if segment.name != "segment" {
+                return Err(XmlError::UnexpectedElement(segment.name.clone()));
+            }
+            let author = segment.parse_attribute::<SessionId>("author")?;
+            Ok(Segment::new(author, segment.text().as_bytes()))
+        })
+        .filter(|s| s.as_ref().map_or(true, |s| !s.is_empty()))
+        .collect::<Result<_, _>>()?;
+    SegmentBuffer::new(segments)
+        .try_with_unit(Unit::Chars)
+        .map_err(|_| XmlError::InvalidText)
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attribute(&self, name: &'static str) -> Result<&str, XmlError> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .ok_or(XmlError::MissingAttribute(name))
+    }
+
+    fn parse_attribute<T: FromStr>(&self, name: &'static str) -> Result<T, XmlError> {
+        let value = self.attribute(name)?;
+        value
+            .parse()
+            .map_err(|_| XmlError::InvalidAttribute(name, value.to_owned()))
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|c| match c {
+            Node::Element(e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+
+    fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|c| match c {
+                Node::Text(t) => Some(t.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// Minimal xml reader, only supports what is used in infinote requests
+struct Parser<'i> {
+    input: &'i str,
+    pos: usize,
+}
+
+impl<'i> Parser<'i> {
+    fn error(&self, msg: &'static str) -> XmlError {
+        XmlError::Syntax(self.pos, msg)
+    }
+
+    fn rest(&self) -> &'i str {
+        &self.input[self.pos..]
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str, msg: &'static str) -> Result<(), XmlError> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(msg))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.input.len() - trimmed.len();
+    }
+
+    fn skip_until(&mut self, end: &str, msg: &'static str) -> Result<(), XmlError> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(self.error(msg)),
+        }
+    }
+
+    fn skip_misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<!--") {
+                self.skip_until("-->", "unterminated comment")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_prolog(&mut self) -> Result<(), XmlError> {
+        self.skip_whitespace();
+        if self.eat("<?xml") {
+            self.skip_until("?>", "unterminated xml declaration")?;
+        }
+        self.skip_misc()
+    }
+
+    fn name(&mut self) -> Result<String, XmlError> {
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+            .unwrap_or_else(|| self.rest().len());
+        if len == 0 {
+            return Err(self.error("expected name"));
+        }
+        let name = self.rest()[..len].to_owned();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn unescape(&self, raw: &str) -> Result<String, XmlError> {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(i) = rest.find('&') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            let end = rest
+                .find(';')
+                .ok_or_else(|| self.error("unterminated entity"))?;
+            let entity = &rest[..end];
+            rest = &rest[end + 1..];
+            let c = match entity {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => {
+                    let code = if let Some(hex) = entity.strip_prefix("#x") {
+                        u32::from_str_radix(hex, 16).ok()
+                    } else if let Some(dec) = entity.strip_prefix('#') {
+                        dec.parse().ok()
+                    } else {
+                        None
+                    };
+                    code.and_then(std::char::from_u32)
+                        .ok_or_else(|| self.error("unknown entity"))?
+                }
+            };
+            out.push(c);
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element, XmlError> {
+        if depth > MAX_DEPTH {
+            return Err(XmlError::TooDeep);
+        }
+        self.expect("<", "expected element")?;
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(Element {
+                    name,
+                    attributes,
+                    children: Vec::new(),
+                });
+            }
+            if self.eat(">") {
+                break;
+            }
+            let attr = self.name()?;
+            self.skip_whitespace();
+            self.expect("=", "expected =")?;
+            self.skip_whitespace();
+            let quote = if self.eat("\"") {
+                "\""
+            } else if self.eat("'") {
+                "'"
+            } else {
+                return Err(self.error("expected quoted attribute value"));
+            };
+            let end = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| self.error("unterminated attribute value"))?;
+            let value = self.unescape(&self.rest()[..end])?;
+            self.pos += end + 1;
+            attributes.push((attr, value));
+        }
+
+        let mut children = Vec::new();
+        loop {
+            if self.eat("</") {
+                let end = self.name()?;
+                if end != name {
+                    return Err(self.error("mismatched closing tag"));
+                }
+                self.skip_whitespace();
+                self.expect(">", "expected >")?;
+                return Ok(Element {
+                    name,
+                    attributes,
+                    children,
+                });
+            } else if self.eat("<!--") {
+                self.skip_until("-->", "unterminated comment")?;
+            } else if self.eat("<![CDATA[") {
+                let end = self
+                    .rest()
+                    .find("]]>")
+                    .ok_or_else(|| self.error("unterminated cdata"))?;
+                children.push(Node::Text(self.rest()[..end].to_owned()));
+                self.pos += end + 3;
+            } else if self.rest().starts_with('<') {
+                children.push(Node::Element(self.element(depth + 1)?));
+            } else if self.rest().is_empty() {
+                return Err(self.error("unterminated element"));
+            } else {
+                let end = self.rest().find('<').unwrap_or_else(|| self.rest().len());
+                let text = self.unescape(&self.rest()[..end])?;
+                self.pos += end;
+                children.push(Node::Text(text));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_xml, from_xml_diff, to_xml, to_xml_diff, XmlError};
+    use crate::{
+        op::{Delete, Insert, Operation},
+        recon::Recon,
+        request::{dor::DoRequest, Request},
+        segment::{Segment, SegmentBuffer},
+        session::SessionInfo,
+        unit::Unit,
+        vector::StateVector,
+        State, NO_OWNER,
+    };
+    use smallvec::smallvec;
+    use std::collections::HashMap;
+
+    fn vector() -> StateVector {
+        let mut vector = StateVector::new();
+        vector.set(1, 3);
+        vector.set(2, 1);
+        vector
+    }
+
+    #[test]
+    fn insert() {
+        let request = Request::Do(DoRequest::new(
+            1,
+            vector(),
+            Insert::new(3, SegmentBuffer::from_bytes(1, b"a<b & c")).into(),
+        ));
+        let xml = to_xml(&request).unwrap();
+        assert_eq!(
+            xml,
+            r#"<request user="1" time="1:3;2:1"><insert pos="3">a&lt;b &amp; c</insert></request>"#
+        );
+        let parsed = from_xml(&xml).unwrap();
+        assert_eq!(to_xml(&parsed).unwrap(), xml);
+    }
+
+    #[test]
+    fn insert_segments() {
+        let xml = r#"<request user="1" time="1:3;2:1"><insert pos="0"><segment author="2">ab</segment><segment author="1">c</segment></insert></request>"#;
+        let parsed = from_xml(xml).unwrap();
+        match parsed.operation() {
+            Some(Operation::Insert(insert)) => assert_eq!(
+                insert.buffer(),
+                &SegmentBuffer::new(smallvec![Segment::new(2, b"ab"), Segment::new(1, b"c")])
+                    .try_with_unit(Unit::Chars)
+                    .unwrap()
+            ),
+            _ => panic!("expected insert"),
+        }
+        assert_eq!(to_xml(&parsed).unwrap(), xml);
+    }
+
+    #[test]
+    fn insert_caret() {
+        let parsed = from_xml(
+            r#"<?xml version="1.0"?>
+            <request user="2" time="1:3">
+                <insert-caret pos="0"> x&#x41;</insert-caret>
+            </request>"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.user(), 2);
+        match parsed.operation() {
+            Some(Operation::Insert(insert)) => {
+                assert_eq!(insert.position, 0);
+                assert_eq!(
+                    insert.buffer(),
+                    &SegmentBuffer::from_text(2, " xA", Unit::Chars)
+                );
+            }
+            _ => panic!("expected insert"),
+        }
+    }
+
+    #[test]
+    fn delete() {
+        let parsed =
+            from_xml(r#"<request user="1" time=""><delete pos="4" len="2"/></request>"#).unwrap();
+        match parsed.operation() {
+            Some(Operation::Delete(delete)) => {
+                assert_eq!(delete.position, 4);
+                assert_eq!(delete.len(), 2);
+                assert!(delete.buf().is_none());
+            }
+            _ => panic!("expected delete"),
+        }
+
+        let request = Request::Do(DoRequest::new(
+            1,
+            vector(),
+            Delete::reversible(
+                4,
+                SegmentBuffer::new(smallvec![Segment::new(1, b"ab"), Segment::new(2, b"c")])
+                    .try_with_unit(Unit::Chars)
+                    .unwrap(),
+                Recon::new(),
+            )
+            .into(),
+        ));
+        let xml = to_xml(&request).unwrap();
+        assert_eq!(
+            xml,
+            r#"<request user="1" time="1:3;2:1"><delete pos="4"><segment author="1">ab</segment><segment author="2">c</segment></delete></request>"#
+        );
+        match from_xml(&xml).unwrap().operation() {
+            Some(Operation::Delete(delete)) => {
+                assert_eq!(
+                    delete.buf(),
+                    request.operation().and_then(|op| match op {
+                        Operation::Delete(d) => d.buf(),
+                        _ => None,
+                    })
+                );
+            }
+            _ => panic!("expected delete"),
+        }
+
+        // Length is counted in characters
+        let parsed = from_xml(
+            r#"<request user="1" time=""><delete pos="0" len="2"><segment author="1">é€</segment></delete></request>"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.operation().unwrap().check(2), Ok(0));
+    }
+
+    #[test]
+    fn undo_redo() {
+        let undo = from_xml(r#"<request user="3" time="1:3;2:1"><undo/></request>"#).unwrap();
+        assert!(matches!(undo, Request::Undo(_)));
+        assert_eq!(undo.vector(), &vector());
+        let redo = from_xml(r#"<request user="3" time="3:1"><redo-caret/></request>"#).unwrap();
+        assert!(matches!(redo, Request::Redo(_)));
+        assert_eq!(
+            to_xml(&undo).unwrap(),
+            r#"<request user="3" time="1:3;2:1"><undo/></request>"#
+        );
+    }
+
+    #[test]
+    fn libinfinity_transcript() {
+        // Requests of two users, as libinfinity sends them to a third one
+        let transcript = [
+            r#"<request user="1" time=""><insert-caret pos="0">Hello</insert-caret></request>"#,
+            r#"<request user="2" time="1:1"><insert-caret pos="5"> world</insert-caret></request>"#,
+            r#"<request user="1" time=""><insert-caret pos="0">¡</insert-caret></request>"#,
+            r#"<request user="2" time="1:1"><delete-caret pos="0" len="1"/></request>"#,
+            r#"<request user="2" time=""><undo-caret/></request>"#,
+            r#"<request user="1" time="2:3"><insert-caret pos="12">!</insert-caret></request>"#,
+        ];
+        let mut state = State::new(SegmentBuffer::from_text(NO_OWNER, "", Unit::Chars));
+        for user in 1..=2 {
+            state.register(user, SessionInfo::default()).unwrap();
+        }
+        let mut previous = HashMap::new();
+        for xml in transcript.iter() {
+            let request =
+                from_xml_diff(xml, |user| previous.get(&user).cloned().unwrap_or_default())
+                    .unwrap();
+            let user = request.user();
+            let before = previous.get(&user).cloned().unwrap_or_default();
+            assert_eq!(
+                to_xml_diff(&request, &before).unwrap(),
+                xml.replace("-caret", "")
+            );
+            let mut after = request.vector().clone();
+            after.add(user, 1);
+            previous.insert(user, after);
+            state.execute(request);
+        }
+        let text = state
+            .buffer
+            .segments()
+            .map(|segment| String::from_utf8(segment.to_vec()).unwrap())
+            .collect::<String>();
+        assert_eq!(text, "¡Hello world!");
+        assert_eq!(
+            state
+                .buffer
+                .authors()
+                .map(|(_, user)| user)
+                .collect::<Vec<_>>(),
+            [1, 2, 1]
+        );
+        assert_eq!(
+            to_xml_diff(&from_xml(transcript[0]).unwrap(), &state.vector),
+            Err(XmlError::TimeBeforePrevious)
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(matches!(
+            from_xml(r#"<request user="1" time=""><move/></request>"#),
+            Err(XmlError::UnexpectedElement(_))
+        ));
+        assert!(matches!(
+            from_xml(r#"<request time=""><undo/></request>"#),
+            Err(XmlError::MissingAttribute("user"))
+        ));
+        assert!(matches!(
+            from_xml(r#"<request user="70000" time=""><undo/></request>"#),
+            Err(XmlError::InvalidAttribute("user", _))
+        ));
+        assert!(matches!(
+            from_xml(r#"<request user="1" time="1:x"><undo/></request>"#),
+            Err(XmlError::InvalidAttribute("time", _))
+        ));
+        assert!(matches!(
+            from_xml(r#"<request user="1" time=""><undo/><redo/></request>"#),
+            Err(XmlError::InvalidOperationCount(2))
+        ));
+        assert!(matches!(
+            from_xml(r#"<request user="1" time=""><undo></request>"#),
+            Err(XmlError::Syntax(..))
+        ));
+        assert!(matches!(
+            from_xml(
+                r#"<request user="1" time=""><delete pos="0" len="3"><segment author="1">a</segment></delete></request>"#
+            ),
+            Err(XmlError::LengthMismatch)
+        ));
+        let nested = format!(
+            r#"<request user="1" time="">{}<no-op/>{}</request>"#,
+            "<split>".repeat(100),
+            "</split>".repeat(100)
+        );
+        assert_eq!(from_xml(&nested).err(), Some(XmlError::TooDeep));
+
+        // Difference, which overflows previous state of user
+        let mut previous = StateVector::new();
+        previous.set(2, 1);
+        let xml = format!(
+            r#"<request user="1" time="2:{}"><undo/></request>"#,
+            usize::MAX
+        );
+        assert!(matches!(
+            from_xml_diff(&xml, |_| previous),
+            Err(XmlError::InvalidAttribute("time", _))
+        ));
+    }
+}
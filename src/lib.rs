@@ -1,15 +1,38 @@
-use std::collections::VecDeque;
+use std::{borrow::Cow, cell::RefCell, collections::VecDeque};
 
-use request::Request;
+use error::OtError;
+use log::RequestLog;
+use op::{Delete, Insert, Operation};
+use recon::Recon;
+use request::{dor::DoRequest, redo::RedoRequest, undo::UndoRequest, Request};
 use segment::SegmentBuffer;
+use session::{SessionInfo, SessionRegistry};
+use translate::TranslationCache;
+use unit::Unit;
 use vector::StateVector;
 
+pub mod codec;
+pub mod error;
+pub mod infinote;
+pub mod log;
 pub mod op;
+mod order;
 pub mod recon;
 pub mod request;
 pub mod segment;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod session;
+#[cfg(any(test, feature = "testing"))]
+pub mod simulation;
+pub mod snapshot;
+mod translate;
+pub mod unit;
 pub mod vector;
 
+#[cfg(feature = "serde")]
+pub use serialize::VERSION as SERDE_VERSION;
+
 /// One user can have multiple sessions, each session - single opened editor
 pub type SessionId = u16;
 /// After compaction all segments are moved to NO_OWNER sessid
@@ -19,91 +42,514 @@ pub const NO_OWNER: SessionId = 0;
 pub type TextSize = usize;
 pub type TextPosition = usize;
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum ConcurrentOrder {
     This,
     Other,
 }
 
+/// How remote request relates to the current state, see [`State::validate`]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Readiness {
+    /// Request can be executed right away
+    Ready,
+    /// Some of the causal predecessors of request are not executed yet
+    MustWait,
+    /// Request is already executed
+    Duplicate,
+}
+
+/// Default limit of undoable requests per session
+pub const DEFAULT_MAX_UNDO: usize = 2048;
+
+/// Default limit of cached translations
+pub const DEFAULT_CACHE_CAPACITY: usize = 4096;
+
 pub struct State {
     pub buffer: SegmentBuffer,
     vector: StateVector,
     request_queue: VecDeque<Request>,
-    log: Vec<Request>,
+    log: RequestLog,
+    /// Already translated requests, entries for request are invalidated once it is logged
+    translated: RefCell<TranslationCache>,
+    sessions: SessionRegistry,
+    /// Whether requests of sessions, which were not registered, are refused
+    strict_sessions: bool,
+    /// Whether session ids are allocated by this peer
+    authority: bool,
+    /// How many last requests of session can be undone
+    max_undo: usize,
 }
 
 impl State {
-    fn translate(&self, request: &Request, target: &StateVector) -> Request {
-        match request {
-            Request::Do(dor) if &dor.vector == target => return Request::Do(dor.clone()),
-            Request::Undo(_) | Request::Redo(_) => {
-                if let Some(assoc) = request.associated_request(&self.log) {
-                    let mut mirror_at = target.clone();
-                    mirror_at.set(request.user(), assoc.vector().get(request.user()));
-
-                    if self.reachable(&mirror_at) {
-                        let translated = self.translate(assoc, &mirror_at);
-                        let mirror_by = target.get(request.user()) - mirror_at.get(request.user());
-
-                        return translated.mirror(mirror_by);
-                    }
-                }
+    /// Positions of state are counted in unit of initial buffer, see
+    /// [`SegmentBuffer::from_text`]
+    pub fn new(initial: SegmentBuffer) -> Self {
+        State {
+            buffer: initial,
+            vector: StateVector::new(),
+            request_queue: VecDeque::new(),
+            log: RequestLog::new(),
+            translated: RefCell::new(TranslationCache::new(DEFAULT_CACHE_CAPACITY)),
+            sessions: SessionRegistry::default(),
+            strict_sessions: true,
+            authority: false,
+            max_undo: DEFAULT_MAX_UNDO,
+        }
+    }
+
+    /// Limits how many last requests of session can be undone, all peers should use
+    /// the same limit
+    pub fn with_max_undo(mut self, max_undo: usize) -> Self {
+        self.max_undo = max_undo;
+        self
+    }
+
+    /// Refuses requests of remote sessions, which were not joined or registered, it is
+    /// enabled by default, without it sessions are registered by their first request
+    pub fn with_strict_sessions(mut self, strict: bool) -> Self {
+        self.strict_sessions = strict;
+        self
+    }
+
+    /// Makes this peer the only one, which allocates session ids, see [`State::join`]
+    pub fn with_authority(mut self, authority: bool) -> Self {
+        self.authority = authority;
+        self
+    }
+
+    /// Limits how many translations are cached
+    pub fn with_cache_capacity(self, capacity: usize) -> Self {
+        self.translated.borrow_mut().set_capacity(capacity);
+        self
+    }
+
+    pub fn vector(&self) -> &StateVector {
+        &self.vector
+    }
+
+    /// Unit of positions in buffer and in every request
+    pub fn unit(&self) -> Unit {
+        self.buffer.unit()
+    }
+
+    /// Executed requests, which are not yet collected as garbage
+    pub fn log(&self) -> &RequestLog {
+        &self.log
+    }
+
+    /// Enqueues remote request, which will be executed by [`State::execute_all`]
+    /// once all of its causal predecessors are executed
+    pub fn queue(&mut self, request: Request) {
+        self.request_queue.push_back(request)
+    }
+
+    /// Classifies remote request against the current state
+    ///
+    /// Requests, which are ready, are translated and checked against the buffer, so
+    /// [`State::try_execute`] won't fail for them. Gaps in request counters can only be
+    /// filled by not yet received requests, so such requests must wait
+    pub fn validate(&self, request: &Request) -> Result<Readiness, OtError> {
+        match self.prepare(request) {
+            Ok(_) => Ok(Readiness::Ready),
+            Err(OtError::NotReady) => Ok(Readiness::MustWait),
+            Err(OtError::Duplicate) => Ok(Readiness::Duplicate),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Validates request, returns its translation to the current state, which is
+    /// applicable to buffer
+    fn prepare(&self, request: &Request) -> Result<DoRequest, OtError> {
+        let (user, n) = (request.user(), request.vector().get(request.user()));
+        self.check_session(user)?;
+        if n < self.vector.get(user) {
+            return Err(OtError::Duplicate);
+        }
+        if !request.vector().casually_before(&self.vector) {
+            return Err(OtError::NotReady);
+        }
+        // Decoded vectors may carry any count, executed request has to increment it
+        n.checked_add(1).ok_or(OtError::VectorOverflow(user))?;
+        if let Request::Undo(_) | Request::Redo(_) = request {
+            if self.associated_request(request).is_none() {
+                return Err(OtError::NoAssociatedRequest);
             }
-            _ => {}
+        }
+        let request = self.in_unit(request)?;
+        // Positions are only checked against the buffer, request was issued for, as
+        // translation doesn't expect them to overflow
+        if let Some(operation) = request.operation() {
+            operation.check(self.len_at(request.vector())?)?;
+        }
+        let translated = match self.try_translate(&request, &self.vector)? {
+            Request::Do(dor) => dor,
+            _ => return Err(OtError::NotTranslated),
         };
+        translated.operation().check(self.buffer.len())?;
+        translated.operation().check_positions(&self.buffer)?;
+        translated.operation().check_deleted(&self.buffer)?;
+        Ok(translated)
+    }
 
-        for session in self.vector.sessions() {
-            if session == request.user() {
+    /// Length of buffer at logged state, requests which are not known there are
+    /// reverted from the current buffer
+    fn len_at(&self, vector: &StateVector) -> Result<TextSize, OtError> {
+        let mut at = vector.clone();
+        let (mut inserted, mut deleted) = (0, 0);
+        for request in self.log.iter() {
+            let user = request.user();
+            if request.vector().get(user) < vector.get(user) {
                 continue;
             }
-            if target.get(session) <= request.vector().get(session) {
-                continue;
+            if let Some(operation) = self.try_translate(request, &at)?.operation() {
+                operation.count_changes(&mut inserted, &mut deleted);
             }
-            let mut last_request = self
-                .request_by_user(session, target.get(session) - 1)
-                .unwrap();
-
-            match last_request {
-                Request::Undo(_) | Request::Redo(_) => {
-                    let fold_by = target.get(session)
-                        - last_request
-                            .associated_request(&self.log)
-                            .unwrap()
-                            .vector()
-                            .get(session);
-
-                    if target.get(session) > fold_by {
-                        let fold_at = {
-                            let mut nv = target.clone();
-                            nv.remove(session, fold_by);
-                            nv
-                        };
-                        if self.reachable(&fold_at) && request.vector().casually_before(&fold_at) {
-                            let translated = self.translate(request, &fold_at);
-                            return translated.fold(session, fold_by);
-                        }
-                    }
+            at.add(user, 1);
+        }
+        self.buffer
+            .len()
+            .checked_add(deleted)
+            .and_then(|len| len.checked_sub(inserted))
+            .ok_or(OtError::InvalidSnapshot)
+    }
+
+    /// Fails for departed sessions, and for unknown ones, unless they may be
+    /// registered by their first request, reserved id is never a session
+    fn check_session(&self, session: SessionId) -> Result<(), OtError> {
+        if session == NO_OWNER {
+            return Err(OtError::UnknownSession(session));
+        }
+        match self.sessions.check(session) {
+            Err(OtError::UnknownSession(_)) if !self.strict_sessions => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Executes every queued request which is ready, returns operations applied to buffer
+    /// and rejected requests in order of execution
+    ///
+    /// Duplicate and invalid requests are dropped from the queue
+    pub fn execute_all(&mut self) -> Vec<Result<Operation, (Request, OtError)>> {
+        let mut executed = Vec::new();
+        loop {
+            let next = self
+                .request_queue
+                .iter()
+                .enumerate()
+                .map(|(idx, request)| (idx, self.prepare(request)))
+                .find(|(_, prepared)| !matches!(prepared, Err(OtError::NotReady)));
+            let (idx, prepared) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            let request = self.request_queue.remove(idx).unwrap();
+            let result = prepared.and_then(|translated| {
+                let request = self.in_unit(&request)?;
+                self.execute_prepared(&request, translated)
+            });
+            executed.push(result.map_err(|e| (request, e)));
+        }
+        executed
+    }
+
+    /// Remote request with text measured in unit of state, received text may be
+    /// measured in any unit
+    fn in_unit<'r>(&self, request: &'r Request) -> Result<Cow<'r, Request>, OtError> {
+        match request.operation() {
+            Some(operation) if !operation.is_in(self.unit()) => {
+                Ok(Cow::Owned(request.try_with_unit(self.unit())?))
+            }
+            _ => Ok(Cow::Borrowed(request)),
+        }
+    }
+
+    /// Inserts text authored by session, returns request to be broadcasted to other peers
+    ///
+    /// # Panics
+    ///
+    /// If session left, or position is out of buffer
+    pub fn local_insert(&mut self, session: SessionId, pos: TextPosition, text: &str) -> Request {
+        let buffer = SegmentBuffer::from_text(session, text, self.unit());
+        self.local(session, Insert::new(pos, buffer).into())
+    }
+
+    /// Deletes text on behalf of session, returns request to be broadcasted to other peers
+    ///
+    /// # Panics
+    ///
+    /// If session left, or range is out of buffer
+    pub fn local_delete(
+        &mut self,
+        session: SessionId,
+        pos: TextPosition,
+        len: TextSize,
+    ) -> Request {
+        let what = self.buffer.slice(pos..pos + len);
+        self.local(session, Delete::reversible(pos, what, Recon::new()).into())
+    }
+
+    /// Whether session has a request to undo, which is still in undo window, see
+    /// [`State::with_max_undo`], departed sessions can't undo
+    pub fn can_undo(&self, session: SessionId) -> bool {
+        !self.departed(session)
+            && self
+                .associated_request(&self.undo_request(session))
+                .is_some()
+    }
+
+    /// Reverts last not yet undone request of session, returns request to be broadcasted
+    pub fn undo(&mut self, session: SessionId) -> Option<Request> {
+        if !self.can_undo(session) {
+            return None;
+        }
+        let request = self.undo_request(session);
+        self.execute_local(request.clone());
+        Some(request)
+    }
+
+    /// Whether session has an undo to revert, departed sessions can't redo
+    pub fn can_redo(&self, session: SessionId) -> bool {
+        !self.departed(session)
+            && self
+                .associated_request(&self.redo_request(session))
+                .is_some()
+    }
+
+    /// Reverts last undo of session, returns request to be broadcasted
+    pub fn redo(&mut self, session: SessionId) -> Option<Request> {
+        if !self.can_redo(session) {
+            return None;
+        }
+        let request = self.redo_request(session);
+        self.execute_local(request.clone());
+        Some(request)
+    }
+
+    fn departed(&self, session: SessionId) -> bool {
+        self.sessions
+            .get(session)
+            .is_some_and(|session| !session.is_present())
+    }
+
+    fn undo_request(&self, session: SessionId) -> Request {
+        Request::Undo(UndoRequest {
+            user: session,
+            vector: self.vector.clone(),
+        })
+    }
+
+    fn redo_request(&self, session: SessionId) -> Request {
+        Request::Redo(RedoRequest {
+            user: session,
+            vector: self.vector.clone(),
+        })
+    }
+
+    fn local(&mut self, session: SessionId, operation: Operation) -> Request {
+        let request = Request::Do(DoRequest::new(session, self.vector.clone(), operation));
+        self.execute_local(request.clone());
+        request
+    }
+
+    /// Local sessions always issue requests at the current state, so they never hold
+    /// back garbage collection
+    fn execute_local(&mut self, request: Request) {
+        let session = request.user();
+        // Local sessions are known, even if they were never registered
+        if self.sessions.get(session).is_none() {
+            let _ = self
+                .sessions
+                .register(session, SessionInfo::default(), self.vector.clone());
+        }
+        self.execute(request);
+        let vector = self.vector.clone();
+        if let Ok(session) = self.sessions.present_mut(session) {
+            session.local = true;
+            session.vector = vector;
+        }
+    }
+
+    /// Every session, which ever joined the document
+    pub fn sessions(&self) -> &SessionRegistry {
+        &self.sessions
+    }
+
+    /// Allocates id for new session, which starts editing at the current state, only
+    /// authority allocates ids, other peers register them, see [`SessionRegistry`]
+    pub fn join(&mut self, info: SessionInfo) -> Result<SessionId, OtError> {
+        if !self.authority {
+            return Err(OtError::NotAuthority);
+        }
+        self.sessions.join(info, self.vector.clone())
+    }
+
+    /// Registers session, which was allocated by authority
+    pub fn register(&mut self, id: SessionId, info: SessionInfo) -> Result<(), OtError> {
+        self.sessions.register(id, info, self.vector.clone())
+    }
+
+    /// Session can't issue requests anymore, but its requests stay in history
+    pub fn leave(&mut self, id: SessionId) -> Result<(), OtError> {
+        self.sessions.leave(id)
+    }
+
+    /// Records, that session has seen every request up to vector, so they are not
+    /// needed to translate its future requests
+    pub fn acknowledge(&mut self, session: SessionId, vector: &StateVector) -> Result<(), OtError> {
+        let session = self.sessions.present_mut(session)?;
+        session.vector = session.vector.lcs(vector);
+        Ok(())
+    }
+
+    /// Drops requests, which were seen by every present session and can't be undone
+    /// anymore, should be called periodically
+    ///
+    /// Remote sessions are known to have seen only requests, they acknowledged, or
+    /// based their own requests on, so sessions, which don't edit, should acknowledge
+    /// received requests, see [`State::acknowledge`]. Sessions, which aren't registered
+    /// yet, are not taken into account
+    pub fn collect_garbage(&mut self) {
+        let bound = self.garbage_bound();
+        self.log.truncate_before(&bound);
+        self.translated.borrow_mut().forget_before(&bound);
+    }
+
+    /// Per-session counter of the first request, which is still needed
+    fn garbage_bound(&self) -> StateVector {
+        let mut bound = self.vector.clone();
+        for vector in self.sessions.known_vectors() {
+            bound = bound.gcp(vector);
+        }
+        for (session, count) in self.vector.iter() {
+            bound.set(
+                session,
+                bound.get(session).min(count.saturating_sub(self.max_undo)),
+            );
+        }
+        // Retained requests may be translated or undone later, so every request
+        // concurrent to them, or associated with them is retained too
+        loop {
+            let mut next = bound.clone();
+            for request in self.log.iter() {
+                if request.vector().get(request.user()) < bound.get(request.user()) {
+                    continue;
                 }
-                _ => {}
+                next = next.gcp(request.vector());
+                if let Some(assoc) = self.associated_request(request) {
+                    next = next.gcp(assoc.vector());
+                }
+            }
+            if next == bound {
+                break;
             }
+            bound = next;
+        }
+        bound
+    }
 
-            let transform_at = {
-                let mut value = target.clone();
-                value.remove(session, 1);
-                value
-            };
+    /// Request reverted by undo/redo, requests past undo limit are never associated,
+    /// so all peers agree on it regardless of collected garbage
+    fn associated_request(&self, request: &Request) -> Option<&Request> {
+        let n = request.vector().get(request.user());
+        request.associated_request(&self.log).filter(|assoc| {
+            assoc
+                .vector()
+                .get(request.user())
+                .saturating_add(self.max_undo)
+                >= n
+        })
+    }
 
-            if transform_at.get(session) >= 0 && self.reachable(&transform_at) {
-                last_request = self
-                    .request_by_user(session, transform_at.get(session))
-                    .unwrap();
+    /// Translates request to the current state and applies it, request should be ready
+    /// for execution
+    ///
+    /// # Panics
+    ///
+    /// If request is invalid, see [`State::try_execute`]
+    pub fn execute(&mut self, request: Request) -> Operation {
+        self.try_execute(request)
+            .unwrap_or_else(|e| panic!("execute: {}", e))
+    }
+
+    /// Translates request to the current state and applies it, state is left unchanged
+    /// on error
+    pub fn try_execute(&mut self, request: Request) -> Result<Operation, OtError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "execute",
+            session = request.user(),
+            vector = %request.vector(),
+            state = %self.vector,
+        )
+        .entered();
+        let request = self.in_unit(&request)?;
+        let translated = self.prepare(&request)?;
+        self.execute_prepared(&request, translated)
+    }
 
-                let r1 = self.translate(request, &transform_at);
-                let r2 = self.translate(last_request, &transform_at);
+    /// Logs and applies request, which is translated by [`State::prepare`]
+    fn execute_prepared(
+        &mut self,
+        request: &Request,
+        translated: DoRequest,
+    ) -> Result<Operation, OtError> {
+        let result = self.execute_translated(request, translated);
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(operation) => tracing::debug!(operation = ?operation, "executed"),
+            Err(e) => tracing::debug!(error = %e, "rejected"),
+        }
+        if result.is_ok() {
+            let user = request.user();
+            let mut seen = request.vector().clone();
+            seen.add(user, 1);
+            match self.sessions.get(user) {
+                Some(_) => self.acknowledge(user, &seen)?,
+                None => self.sessions.register(user, SessionInfo::default(), seen)?,
             }
         }
+        result
+    }
+
+    fn execute_translated(
+        &mut self,
+        request: &Request,
+        translated: DoRequest,
+    ) -> Result<Operation, OtError> {
+        let logged = match request {
+            // Request may be mirrored later, so it should be logged in reversible form
+            Request::Do(dor) => Request::Do(dor.make_reversible(&translated, self)?),
+            request => request.clone(),
+        };
+        self.log.push(logged);
+
+        translated.execute(self);
+        Ok(translated.operation().clone())
+    }
 
-        todo!()
+    /// Transforms request to be applicable at target state, which should be reachable
+    /// from the request's own state
+    ///
+    /// # Panics
+    ///
+    /// If request can't be translated, see [`State::try_translate`]
+    pub fn translate(&self, request: &Request, target: &StateVector) -> Request {
+        self.try_translate(request, target)
+            .unwrap_or_else(|e| panic!("translate: {}", e))
+    }
+
+    pub fn try_translate(
+        &self,
+        request: &Request,
+        target: &StateVector,
+    ) -> Result<Request, OtError> {
+        if let Request::Do(dor) = request {
+            if &dor.vector == target {
+                return Ok(request.clone());
+            }
+        }
+        self.translate_iterative(request, target)
     }
 
     fn reachable(&self, target: &StateVector) -> bool {
@@ -114,7 +560,7 @@ impl State {
 
     fn reachable_user(&self, target: &StateVector, user: SessionId) -> bool {
         let mut n = target.get(user);
-        let first_request = self.first_request_by(user);
+        let first_request = self.log.first(user);
         let first_request_number = if let Some(v) = first_request {
             v.vector().get(user)
         } else {
@@ -125,7 +571,7 @@ impl State {
             if n == first_request_number {
                 return true;
             }
-            if let Some(r) = self.request_by_user(user, n - 1) {
+            if let Some(r) = n.checked_sub(1).and_then(|n| self.log.get(user, n)) {
                 match r {
                     Request::Do(dor) => {
                         let mut w = dor.vector.clone();
@@ -133,11 +579,10 @@ impl State {
                         return w.casually_before(target);
                     }
                     Request::Redo(_) | Request::Undo(_) => {
-                        if let Some(v) = r
-                            .associated_request(&self.log)
-                            .map(|r| r.vector().get(user))
-                        {
+                        if let Some(v) = self.associated_request(r).map(|r| r.vector().get(user)) {
                             n = v;
+                        } else {
+                            return false;
                         }
                     }
                 }
@@ -146,18 +591,531 @@ impl State {
             }
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::OtError,
+        op::{Delete, Insert},
+        recon::Recon,
+        request::{dor::DoRequest, redo::RedoRequest, undo::UndoRequest, Request},
+        segment::{Segment, SegmentBuffer},
+        session::SessionInfo,
+        unit::Unit,
+        vector::StateVector,
+        Readiness, SessionId, State, NO_OWNER,
+    };
+    use smallvec::smallvec;
+
+    fn buf(user: SessionId, text: &str) -> SegmentBuffer {
+        SegmentBuffer::new(smallvec![Segment::new(user, text.as_bytes())])
+    }
+
+    /// State, which accepts requests of sessions, joined before any edit
+    fn peer(initial: SegmentBuffer) -> State {
+        let mut state = State::new(initial);
+        for session in 1..=3 {
+            state.register(session, SessionInfo::default()).unwrap();
+        }
+        state
+    }
+
+    fn insert(user: SessionId, vector: &StateVector, pos: usize, text: &str) -> Request {
+        Request::Do(DoRequest::new(
+            user,
+            vector.clone(),
+            Insert::new(pos, buf(user, text)).into(),
+        ))
+    }
+
+    fn delete(user: SessionId, vector: &StateVector, pos: usize, len: usize) -> Request {
+        Request::Do(DoRequest::new(
+            user,
+            vector.clone(),
+            Delete::new(pos, Err(len), Recon::new()).into(),
+        ))
+    }
+
+    #[test]
+    fn concurrent_insert_delete() {
+        let mut a = peer(buf(NO_OWNER, "abcdef"));
+        let mut b = peer(buf(NO_OWNER, "abcdef"));
+
+        let r1 = insert(1, a.vector(), 3, "X");
+        let r2 = delete(2, b.vector(), 1, 4);
+
+        a.execute(r1.clone());
+        a.execute(r2.clone());
+        b.execute(r2);
+        b.execute(r1);
+
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(a.vector(), b.vector());
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(NO_OWNER, b"a"),
+                Segment::new(1, b"X"),
+                Segment::new(NO_OWNER, b"f"),
+            ])
+        );
+    }
+
+    #[test]
+    fn concurrent_inserts_at_same_position() {
+        let mut a = peer(buf(NO_OWNER, "ab"));
+        let mut b = peer(buf(NO_OWNER, "ab"));
+
+        let r1 = insert(1, a.vector(), 1, "a");
+        let r2 = insert(2, b.vector(), 1, "b");
+
+        a.execute(r1.clone());
+        a.execute(r2.clone());
+        b.execute(r2);
+        b.execute(r1);
+
+        // Tied inserts are ordered by their text
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(NO_OWNER, b"a"),
+                Segment::new(1, b"a"),
+                Segment::new(2, b"b"),
+                Segment::new(NO_OWNER, b"b"),
+            ])
+        );
+    }
+
+    #[test]
+    fn local_edits() {
+        let mut a = peer(buf(NO_OWNER, "hello"));
+        let mut b = peer(buf(NO_OWNER, "hello"));
+
+        let r1 = a.local_insert(1, 5, " world");
+        let r2 = b.local_delete(2, 0, 5);
+        let r3 = b.local_insert(2, 0, "bye");
 
-    fn user_requests(&self, user: SessionId) -> impl Iterator<Item = &Request> {
-        self.log.iter().filter(move |r| r.user() == user)
+        b.queue(r1);
+        b.execute_all();
+        a.queue(r3);
+        a.queue(r2);
+        assert_eq!(a.execute_all().len(), 2);
+
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(2, b"bye"),
+                Segment::new(1, b" world"),
+            ])
+        );
     }
 
-    fn request_by_user(&self, user: SessionId, get_index: usize) -> Option<&Request> {
-        self.user_requests(user)
-            .find(|r| r.vector().get(user) == get_index)
+    #[test]
+    fn undo_redo() {
+        let mut a = peer(buf(NO_OWNER, "abc"));
+        let mut b = peer(buf(NO_OWNER, "abc"));
+        assert!(!a.can_undo(1));
+
+        let r1 = a.local_delete(1, 0, 2);
+        let r2 = b.local_insert(2, 1, "X");
+        let r3 = a.undo(1).unwrap();
+        assert!(!a.can_undo(1));
+        assert!(a.can_redo(1));
+        assert!(!b.can_redo(2));
+
+        a.queue(r2);
+        a.execute_all();
+        b.queue(r1);
+        b.queue(r3);
+        b.execute_all();
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(NO_OWNER, b"a"),
+                Segment::new(2, b"X"),
+                Segment::new(NO_OWNER, b"bc"),
+            ])
+        );
+
+        let r4 = a.redo(1).unwrap();
+        let r5 = b.undo(2).unwrap();
+        a.queue(r5);
+        a.execute_all();
+        b.queue(r4);
+        b.execute_all();
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(a.buffer, buf(NO_OWNER, "c"));
     }
 
-    fn first_request_by(&self, user: SessionId) -> Option<&Request> {
-        self.user_requests(user)
-            .min_by_key(|r| r.vector().get(user))
+    #[test]
+    fn queue_waits_for_predecessors() {
+        let mut origin = peer(buf(NO_OWNER, "ab"));
+        let r1 = insert(1, origin.vector(), 0, "X");
+        origin.execute(r1.clone());
+        let r2 = insert(1, origin.vector(), 3, "Y");
+        origin.execute(r2.clone());
+
+        let mut state = peer(buf(NO_OWNER, "ab"));
+        state.queue(r2);
+        assert!(state.execute_all().is_empty());
+        state.queue(r1.clone());
+        assert!(state.execute_all().iter().all(Result::is_ok));
+        assert_eq!(state.buffer, origin.buffer);
+
+        // Rejected requests are returned, so they can be reported
+        state.queue(r1);
+        state.queue(insert(2, origin.vector(), 9, "Z"));
+        let rejected = state
+            .execute_all()
+            .into_iter()
+            .map(|result| result.err().map(|(request, e)| (request.user(), e)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rejected,
+            [
+                Some((1, OtError::Duplicate)),
+                Some((2, OtError::OutOfRange { end: 9, len: 4 }))
+            ]
+        );
+        assert_eq!(state.buffer, origin.buffer);
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let mut state = peer(buf(NO_OWNER, "abc"));
+        state.execute(insert(1, &StateVector::new(), 3, "d"));
+        let before = state.buffer.clone();
+
+        // Concurrent delete overlapping the end of buffer
+        assert!(matches!(
+            state.try_execute(delete(2, &StateVector::new(), 2, 5)),
+            Err(OtError::OutOfRange { .. })
+        ));
+        assert_eq!(
+            state.try_execute(insert(2, state.vector(), 6, "x")).err(),
+            Some(OtError::OutOfRange { end: 6, len: 4 })
+        );
+        // Concurrent requests, which positions would overflow during translation
+        assert!(matches!(
+            state.try_execute(delete(2, &StateVector::new(), 2, usize::MAX)),
+            Err(OtError::OutOfRange { .. })
+        ));
+        assert_eq!(
+            state
+                .try_execute(insert(2, &StateVector::new(), usize::MAX, "x"))
+                .err(),
+            Some(OtError::OutOfRange {
+                end: usize::MAX,
+                len: 3
+            })
+        );
+
+        let mut ahead = StateVector::new();
+        ahead.set(3, 1);
+        assert_eq!(
+            state.try_execute(insert(2, &ahead, 0, "x")).err(),
+            Some(OtError::NotReady)
+        );
+        assert_eq!(
+            state
+                .try_execute(Request::Undo(UndoRequest {
+                    user: 2,
+                    vector: state.vector().clone(),
+                }))
+                .err(),
+            Some(OtError::NoAssociatedRequest)
+        );
+
+        // Rejected requests leave state untouched
+        assert_eq!(state.buffer, before);
+        assert_eq!(state.vector().to_string(), "1:1");
+        state.execute(insert(2, state.vector(), 4, "e"));
+        assert_eq!(state.vector().to_string(), "1:1;2:1");
+    }
+
+    #[test]
+    fn deleted_text_is_checked() {
+        let mut state = peer(buf(NO_OWNER, "abcd"));
+        state.register(7, SessionInfo::default()).unwrap();
+        let reversible = |vector: &StateVector, pos: usize, text: SegmentBuffer| {
+            let operation = Delete::reversible(pos, text, Recon::new()).into();
+            Request::Do(DoRequest::new(7, vector.clone(), operation))
+        };
+
+        assert_eq!(
+            state
+                .try_execute(reversible(state.vector(), 1, buf(7, "zz")))
+                .err(),
+            Some(OtError::DeletedTextMismatch)
+        );
+        // Same text by another author
+        assert_eq!(
+            state
+                .try_execute(reversible(state.vector(), 1, buf(7, "bc")))
+                .err(),
+            Some(OtError::DeletedTextMismatch)
+        );
+        assert_eq!(state.buffer, buf(NO_OWNER, "abcd"));
+
+        // Text, deleted or inserted concurrently, is accounted for
+        let base = StateVector::new();
+        state.execute(insert(1, &base, 2, "x"));
+        state.execute(delete(2, &base, 0, 1));
+        state.execute(reversible(&base, 0, buf(NO_OWNER, "abc")));
+        assert_eq!(
+            state.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(1, b"x"),
+                Segment::new(NO_OWNER, b"d"),
+            ])
+        );
+    }
+
+    #[test]
+    fn sessions() {
+        let mut state = State::new(buf(NO_OWNER, "abc"));
+        assert_eq!(
+            state.try_execute(insert(5, state.vector(), 0, "z")).err(),
+            Some(OtError::UnknownSession(5))
+        );
+        state.register(5, SessionInfo::default()).unwrap();
+        state.execute(insert(5, state.vector(), 0, "z"));
+        // Local sessions don't have to be registered
+        state.local_insert(1, 0, "x");
+        assert_eq!(state.buffer.len(), 5);
+        assert!(state.sessions().get(1).unwrap().is_present());
+        assert!(state.sessions().get(5).unwrap().is_present());
+
+        state.leave(5).unwrap();
+        assert_eq!(
+            state.try_execute(insert(5, state.vector(), 0, "y")).err(),
+            Some(OtError::DepartedSession(5))
+        );
+
+        // Unknown sessions are registered by their first request, once allowed
+        let mut lenient = State::new(buf(NO_OWNER, "abc")).with_strict_sessions(false);
+        lenient.execute(insert(2, lenient.vector(), 0, "y"));
+        assert!(lenient.sessions().get(2).unwrap().is_present());
+
+        // Reserved id is never a session
+        for state in [&mut state, &mut lenient] {
+            assert_eq!(
+                state
+                    .try_execute(insert(NO_OWNER, state.vector(), 0, "w"))
+                    .err(),
+                Some(OtError::UnknownSession(NO_OWNER))
+            );
+        }
+        assert_eq!(lenient.buffer.len(), 4);
+    }
+
+    #[test]
+    fn validate_classifies_requests() {
+        let mut origin = peer(buf(NO_OWNER, "ab"));
+        let r1 = insert(1, origin.vector(), 0, "X");
+        origin.execute(r1.clone());
+        let r2 = insert(2, origin.vector(), 3, "Y");
+
+        let mut state = peer(buf(NO_OWNER, "ab"));
+        assert_eq!(state.validate(&r2), Ok(Readiness::MustWait));
+        assert_eq!(state.validate(&r1), Ok(Readiness::Ready));
+        state.execute(r1.clone());
+        assert_eq!(state.validate(&r1), Ok(Readiness::Duplicate));
+        assert_eq!(state.try_execute(r1).err(), Some(OtError::Duplicate));
+        assert_eq!(state.validate(&r2), Ok(Readiness::Ready));
+        assert_eq!(
+            state.validate(&insert(2, state.vector(), 4, "Y")),
+            Err(OtError::OutOfRange { end: 4, len: 3 })
+        );
+        let redo = Request::Redo(RedoRequest {
+            user: 1,
+            vector: state.vector().clone(),
+        });
+        assert_eq!(state.validate(&redo), Err(OtError::NoAssociatedRequest));
+        assert_eq!(redo.try_mirror(2).err(), Some(OtError::NotTranslated));
+    }
+
+    #[test]
+    fn validate_does_not_cache_unlogged_requests() {
+        let mut a = peer(buf(NO_OWNER, "ab"));
+        let mut b = peer(buf(NO_OWNER, "ab"));
+        let base = StateVector::new();
+        a.local_insert(1, 0, "X");
+        b.local_insert(1, 0, "X");
+        assert_eq!(a.validate(&insert(2, &base, 1, "Y")), Ok(Readiness::Ready));
+        // Same session and counter, but another operation
+        a.execute(insert(2, &base, 2, "Z"));
+        b.execute(insert(2, &base, 2, "Z"));
+        assert_eq!(a.buffer, b.buffer);
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let mut a = State::new(SegmentBuffer::default()).with_max_undo(1);
+        let mut b = State::new(SegmentBuffer::default()).with_max_undo(1);
+        a.register(2, SessionInfo::default()).unwrap();
+        b.register(1, SessionInfo::default()).unwrap();
+
+        let r1 = a.local_insert(1, 0, "x");
+        let r2 = a.local_insert(1, 1, "y");
+        b.execute(r1);
+        b.execute(r2);
+        let r3 = b.local_insert(2, 2, "z");
+        a.execute(r3);
+
+        // Everything is seen by both sessions, but the last request of session 1
+        // can still be undone
+        a.collect_garbage();
+        assert_eq!(a.log.len(), 2);
+        assert!(a.can_undo(1));
+
+        let r4 = a.undo(1).unwrap();
+        assert!(!a.can_undo(1));
+        let r5 = b.local_insert(2, 0, "w");
+        a.execute(r5);
+        b.execute(r4);
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(2, b"w"),
+                Segment::new(1, b"x"),
+                Segment::new(2, b"z"),
+            ])
+        );
+    }
+
+    #[test]
+    fn concurrent_inserts_of_same_text() {
+        let mut a = peer(buf(NO_OWNER, "ab"));
+        let mut b = peer(buf(NO_OWNER, "ab"));
+
+        let r1 = insert(1, a.vector(), 1, "x");
+        let r2 = insert(2, b.vector(), 1, "x");
+
+        a.execute(r1.clone());
+        a.execute(r2.clone());
+        b.execute(r2);
+        b.execute(r1);
+
+        // Insert of lower session is shifted
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(NO_OWNER, b"a"),
+                Segment::new(2, b"x"),
+                Segment::new(1, b"x"),
+                Segment::new(NO_OWNER, b"b"),
+            ])
+        );
+    }
+
+    #[test]
+    fn garbage_collection_waits_for_silent_sessions() {
+        let mut a = State::new(SegmentBuffer::default()).with_max_undo(0);
+        a.register(3, SessionInfo::default()).unwrap();
+        let joined = a.vector().clone();
+        a.local_insert(1, 0, "x");
+        a.local_insert(1, 1, "y");
+
+        // Session 3 may still issue requests concurrent to both inserts
+        a.collect_garbage();
+        assert_eq!(a.log.len(), 2);
+        a.execute(insert(3, &joined, 0, "z"));
+        a.collect_garbage();
+        assert_eq!(a.log.len(), 3);
+
+        let seen = a.vector().clone();
+        a.acknowledge(3, &seen).unwrap();
+        a.collect_garbage();
+        assert_eq!(a.log.len(), 0);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![Segment::new(1, b"xy"), Segment::new(3, b"z")])
+        );
+    }
+
+    #[test]
+    fn utf16_positions() {
+        let initial = SegmentBuffer::from_text(NO_OWNER, "a😀b", Unit::Utf16);
+        let mut a = peer(initial.clone());
+        let mut b = peer(initial.clone());
+        assert_eq!(a.unit(), Unit::Utf16);
+        assert_eq!(a.buffer.len(), 4);
+
+        let r1 = a.local_insert(1, 3, "é");
+        let r2 = b.local_delete(2, 1, 2);
+        // Sender's units are not known on the wire, text is measured on receive
+        let r2 = r2.try_with_unit(Unit::Bytes).unwrap();
+        a.execute(r2);
+        b.execute(r1);
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(
+            a.buffer,
+            SegmentBuffer::new(smallvec![
+                Segment::new(NO_OWNER, b"a"),
+                Segment::new(1, "é".as_bytes()),
+                Segment::new(NO_OWNER, b"b"),
+            ])
+            .try_with_unit(Unit::Utf16)
+            .unwrap()
+        );
+        assert_eq!(a.buffer.len_in(Unit::Bytes), 4);
+        assert_eq!(a.buffer.convert(2, Unit::Utf16, Unit::Bytes), Ok(3));
+
+        // Requests can't split surrogate pair
+        let mut c = peer(initial);
+        assert_eq!(
+            c.validate(&delete(1, c.vector(), 2, 1)),
+            Err(OtError::InsideCodePoint(2))
+        );
+        assert_eq!(
+            c.try_execute(insert(1, c.vector(), 2, "x")).err(),
+            Some(OtError::InsideCodePoint(2))
+        );
+        assert_eq!(c.buffer.len(), 4);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{span, subscriber::with_default, Event, Metadata, Subscriber};
+
+        /// Records names of created spans
+        struct Spans(Arc<Mutex<Vec<&'static str>>>);
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                span::Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let names = Arc::new(Mutex::new(Vec::new()));
+        with_default(Spans(names.clone()), || {
+            let mut a = peer(buf(NO_OWNER, "abc"));
+            let mut b = peer(buf(NO_OWNER, "abc"));
+            a.local_insert(1, 1, "x");
+            let r2 = b.local_delete(2, 0, 2);
+            a.execute(r2);
+        });
+        let names = names.lock().unwrap();
+        for name in ["execute", "translate", "transform", "splice"].iter() {
+            assert!(names.contains(name), "{} span in {:?}", name, names);
+        }
     }
 }
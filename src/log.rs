@@ -0,0 +1,151 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{request::Request, vector::StateVector, SessionId};
+
+/// Requests of single session, ordered by their own counter
+#[derive(Clone, Default)]
+struct SessionLog {
+    /// Counter of the first retained request, earlier ones are collected as garbage
+    offset: usize,
+    requests: VecDeque<Request>,
+}
+
+/// Executed requests, indexed by session and per-session counter
+#[derive(Clone, Default)]
+pub struct RequestLog {
+    sessions: HashMap<SessionId, SessionLog>,
+    /// Execution order, which is always causal
+    order: VecDeque<(SessionId, usize)>,
+}
+
+impl RequestLog {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Appends executed request, requests of each session should be pushed in order
+    /// of their counters
+    pub fn push(&mut self, request: Request) {
+        let (user, n) = (request.user(), request.vector().get(request.user()));
+        let session = self.sessions.entry(user).or_insert_with(|| SessionLog {
+            offset: n,
+            requests: VecDeque::new(),
+        });
+        debug_assert_eq!(session.offset + session.requests.len(), n);
+        session.requests.push_back(request);
+        self.order.push_back((user, n));
+    }
+
+    /// Request of session, which was issued after `n` other requests of this session
+    pub fn get(&self, session: SessionId, n: usize) -> Option<&Request> {
+        let session = self.sessions.get(&session)?;
+        session.requests.get(n.checked_sub(session.offset)?)
+    }
+
+    /// Earliest retained request of session
+    pub fn first(&self, session: SessionId) -> Option<&Request> {
+        self.sessions.get(&session)?.requests.front()
+    }
+
+    pub fn last(&self, session: SessionId) -> Option<&Request> {
+        self.sessions.get(&session)?.requests.back()
+    }
+
+    /// Retained requests of session, which were issued before its `n`-th request
+    pub fn before(
+        &self,
+        session: SessionId,
+        n: usize,
+    ) -> impl DoubleEndedIterator<Item = &Request> + '_ {
+        self.sessions
+            .get(&session)
+            .into_iter()
+            .flat_map(move |session| {
+                let end = n.saturating_sub(session.offset).min(session.requests.len());
+                session.requests.range(..end)
+            })
+    }
+
+    /// Iterates over requests in causal order
+    pub fn iter(&self) -> impl Iterator<Item = &Request> + '_ {
+        self.order
+            .iter()
+            .filter_map(move |(session, n)| self.get(*session, *n))
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Drops requests of every session, which were issued before its component of bound
+    pub(crate) fn truncate_before(&mut self, bound: &StateVector) {
+        for (user, session) in self.sessions.iter_mut() {
+            let end = bound
+                .get(*user)
+                .saturating_sub(session.offset)
+                .min(session.requests.len());
+            session.requests.drain(..end);
+            session.offset += end;
+        }
+        self.sessions
+            .retain(|_, session| !session.requests.is_empty());
+        self.order.retain(|(user, n)| *n >= bound.get(*user));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestLog;
+    use crate::{
+        op::Operation,
+        request::{dor::DoRequest, Request},
+        vector::StateVector,
+        SessionId,
+    };
+
+    fn request(user: SessionId, vector: &str) -> Request {
+        Request::Do(DoRequest::new(
+            user,
+            vector.parse().unwrap(),
+            Operation::NoOp,
+        ))
+    }
+
+    fn key(request: &Request) -> String {
+        format!("{}@{}", request.user(), request.vector())
+    }
+
+    #[test]
+    fn lookups_survive_truncation() {
+        let mut log = RequestLog::new();
+        log.push(request(1, ""));
+        log.push(request(2, "1:1"));
+        log.push(request(1, "1:1;2:1"));
+        log.push(request(1, "1:2;2:1"));
+        assert_eq!(log.len(), 4);
+        assert_eq!(key(log.get(1, 1).unwrap()), "1@1:1;2:1");
+        assert_eq!(
+            log.before(1, 2).map(key).collect::<Vec<_>>(),
+            ["1@", "1@1:1;2:1"]
+        );
+
+        log.truncate_before(&"1:2;2:1".parse::<StateVector>().unwrap());
+        assert_eq!(log.len(), 1);
+        assert!(log.get(1, 1).is_none());
+        assert!(log.first(2).is_none());
+        assert_eq!(key(log.get(1, 2).unwrap()), "1@1:2;2:1");
+        assert_eq!(key(log.first(1).unwrap()), key(log.last(1).unwrap()));
+        assert_eq!(log.before(1, 2).count(), 0);
+
+        log.push(request(2, "1:3;2:1"));
+        assert_eq!(
+            log.iter().map(key).collect::<Vec<_>>(),
+            ["1@1:2;2:1", "2@1:3;2:1"]
+        );
+        assert_eq!(key(log.get(2, 1).unwrap()), "2@1:3;2:1");
+    }
+}
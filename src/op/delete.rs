@@ -1,11 +1,14 @@
-use std::ops::RangeBounds;
+use std::ops::Range;
 
-use crate::{recon::Recon, segment::SegmentBuffer, ConcurrentOrder, State, TextPosition, TextSize};
+use crate::{
+    error::OtError, recon::Recon, segment::SegmentBuffer, unit::Unit, ConcurrentOrder, State,
+    TextPosition, TextSize,
+};
 
-use super::{insert::Insert, Operation, Split};
+use super::{end_of, insert::Insert, Operation, Split};
 use anyhow::Result;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Delete {
     pub position: TextPosition,
     pub what: Result<SegmentBuffer, TextSize>,
@@ -46,19 +49,35 @@ impl Delete {
         }
     }
 
-    fn range(&self) -> impl RangeBounds<TextPosition> {
-        self.position..self.position + self.len()
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn range(&self) -> Result<Range<TextPosition>, OtError> {
+        Ok(self.position..end_of(self.position, self.len())?)
     }
 
     pub fn apply(&self, buf: &mut SegmentBuffer) {
-        buf.splice(self.range(), None)
+        let range = self.range().unwrap_or_else(|e| panic!("apply: {}", e));
+        buf.splice(range, None)
+    }
+
+    /// Length of buffer after applying this operation to buffer of given length
+    pub fn check(&self, len: TextSize) -> Result<TextSize, OtError> {
+        match self.position.checked_add(self.len()) {
+            Some(end) if end <= len => Ok(len - self.len()),
+            end => Err(OtError::OutOfRange {
+                end: end.unwrap_or(TextPosition::MAX),
+                len,
+            }),
+        }
     }
 
     fn split(&self, at: TextPosition) -> (Self, Self) {
         match &self.what {
             Ok(buf) => (
                 Delete::reversible(self.position, buf.slice(0..at), Recon::new()),
-                Delete::reversible(self.position, buf.slice(at..), Recon::new()),
+                Delete::reversible(self.position + at, buf.slice(at..), Recon::new()),
             ),
             Err(len) => {
                 let (rec1, rec2) = self.recon.split_at(at);
@@ -71,65 +90,76 @@ impl Delete {
         }
     }
 
-    fn get_affected(operation: &Operation, buf: &SegmentBuffer) -> SegmentBuffer {
-        match operation {
+    fn get_affected(operation: &Operation, buf: &SegmentBuffer) -> Result<SegmentBuffer, OtError> {
+        Ok(match operation {
+            Operation::NoOp => SegmentBuffer::default(),
             Operation::Delete(delete) => {
-                let mut recon_buf = buf.slice(delete.position..delete.position + delete.len());
-                delete.recon.restore(&mut recon_buf);
+                let mut recon_buf = buf.try_slice(delete.range()?)?;
+                delete.recon.try_restore(&mut recon_buf)?;
                 recon_buf
             }
             Operation::Split(split) => {
-                let a = Delete::get_affected(&split.0, buf);
-                let mut b = Delete::get_affected(&split.1, buf);
+                let a = Delete::get_affected(&split.0, buf)?;
+                let mut b = Delete::get_affected(&split.1, buf)?;
                 b.splice(0..0, Some(a));
                 b
             }
-            _ => panic!("unknown op"),
-        }
+            // Transformed delete consists of deletes only
+            Operation::Insert(_) => return Err(OtError::UnexpectedInsert),
+        })
     }
 
-    pub fn make_reversible(&self, transformed: &Operation, state: &State) -> Delete {
-        match &self.what {
+    pub fn make_reversible(
+        &self,
+        transformed: &Operation,
+        state: &State,
+    ) -> Result<Delete, OtError> {
+        Ok(match &self.what {
             Ok(buf) => Delete::reversible(self.position, buf.clone(), Recon::new()),
             Err(_) => Delete::reversible(
                 self.position,
-                Delete::get_affected(transformed, &state.buffer),
+                Delete::get_affected(transformed, &state.buffer)?,
                 Recon::new(),
             ),
-        }
+        })
     }
 
-    fn merge(&self, other: &Delete) -> Delete {
-        if let Ok(buf) = &self.what {
-            if let Ok(other_buf) = &other.what {
-                if !other.is_reversible() {}
+    fn merge(&self, other: &Delete) -> Result<Delete, OtError> {
+        match (&self.what, &other.what) {
+            (Ok(buf), Ok(other_buf)) => {
                 let mut new_buf = buf.clone();
                 new_buf.splice(new_buf.len()..new_buf.len(), Some(other_buf.clone()));
 
-                Delete::reversible(self.position, new_buf, Recon::new())
-            } else {
-                panic!("cannot merge reversible operation with non-reversible");
+                Ok(Delete::reversible(self.position, new_buf, Recon::new()))
+            }
+            (Ok(_), Err(_)) => Err(OtError::MixedReversibility),
+            (Err(_), _) => {
+                let new_len = self.len() + other.len();
+                Ok(Delete::nonreversible(self.position, new_len, Recon::new()))
             }
-        } else {
-            let new_len = self.len() + other.len();
-            Delete::nonreversible(self.position, new_len, Recon::new())
         }
     }
 
-    pub(crate) fn transform(&self, other: &Operation, cid: Option<ConcurrentOrder>) -> Operation {
-        let cid = cid.unwrap();
-        match other {
+    pub(crate) fn try_transform(
+        &self,
+        other: &Operation,
+        cid: Option<ConcurrentOrder>,
+    ) -> Result<Operation, OtError> {
+        Ok(match other {
             Operation::NoOp => self.clone().into(),
             Operation::Delete(other) => {
+                let other_buf = || other.buf().ok_or(OtError::NonReversible);
                 let pos1 = self.position;
                 let pos2 = other.position;
                 let len1 = self.len();
                 let len2 = other.len();
-                if pos1 + len1 <= pos2 {
+                let end1 = end_of(pos1, len1)?;
+                let end2 = end_of(pos2, len2)?;
+                if end1 <= pos2 {
                     Delete::new(pos1, self.what.clone(), self.recon.clone()).into()
-                } else if pos1 >= pos2 + len2 {
+                } else if pos1 >= end2 {
                     Delete::new(pos1 - len2, self.what.clone(), self.recon.clone()).into()
-                } else if pos2 <= pos1 && pos2 + len2 >= pos1 + len1 {
+                } else if pos2 <= pos1 && end2 >= end1 {
                     //     1XXXXX|
                     // 2-------------|
                     //
@@ -138,39 +168,30 @@ impl Delete {
                     // operation removes nothing.
 
                     let mut new_recon = self.recon.clone();
-                    new_recon.add(
-                        0,
-                        other
-                            .buf()
-                            .expect("reversible other")
-                            .slice(pos1 - pos2..pos1 - pos2 + len1),
-                    );
+                    new_recon.add(0, other_buf()?.try_slice(pos1 - pos2..end1 - pos2)?);
 
                     if self.is_reversible() {
                         // TODO: Is new segment buffer correct?
-                        Self::reversible(pos2, SegmentBuffer::new(smallvec::smallvec![]), new_recon)
+                        Self::reversible(pos2, SegmentBuffer::default(), new_recon)
                     } else {
                         Self::nonreversible(pos2, 0, new_recon)
                     }
                     .into()
-                } else if pos2 <= pos1 && pos2 + len2 < pos1 + len1 {
+                } else if pos2 <= pos1 && end2 < end1 {
                     //     1XXXX----|
                     // 2--------|
                     //
                     // The first part of this operation falls within the range of
                     // another.
-                    let (_, mut result) = self.split(pos2 + len2 - pos1);
+                    let (_, mut result) = self.split(end2 - pos1);
                     result.position = pos2;
                     result.recon = {
                         let mut new_recon = self.recon.clone();
-                        new_recon.add(
-                            0,
-                            other.buf().expect("reversible other").slice(pos1 - pos2..),
-                        );
+                        new_recon.add(0, other_buf()?.try_slice(pos1 - pos2..)?);
                         new_recon
                     };
                     result.into()
-                } else if pos2 > pos1 && pos2 + len2 >= pos1 + len1 {
+                } else if pos2 > pos1 && end2 >= end1 {
                     // 1----XXXXX|
                     //     2--------|
                     //
@@ -179,17 +200,11 @@ impl Delete {
                     let (mut result, _) = self.split(pos2 - pos1);
                     result.recon = {
                         let mut new_recon = self.recon.clone();
-                        new_recon.add(
-                            result.len(),
-                            other
-                                .buf()
-                                .expect("reversible other")
-                                .slice(0..pos1 + len1 - pos2),
-                        );
+                        new_recon.add(result.len(), other_buf()?.try_slice(0..end1 - pos2)?);
                         new_recon
                     };
                     result.into()
-                } else if pos2 > pos1 && pos2 + len2 < pos1 + len1 {
+                } else {
                     // 1-----XXXXXX---|
                     //      2------|
                     //
@@ -198,16 +213,14 @@ impl Delete {
                     let (r1, r2) = self.split(pos2 - pos1);
                     let (_, r2) = r2.split(len2);
 
-                    let mut result = r1.merge(&r2);
+                    let mut result = r1.merge(&r2)?;
 
                     result.recon = {
                         let mut new_recon = self.recon.clone();
-                        new_recon.add(pos2 - pos1, other.buf().expect("reversible other").clone());
+                        new_recon.add(pos2 - pos1, other_buf()?.clone());
                         new_recon
                     };
                     result.into()
-                } else {
-                    unreachable!()
                 }
             }
             Operation::Insert(insert) => {
@@ -215,34 +228,40 @@ impl Delete {
                 let len1 = self.len();
                 let pos2 = insert.position;
                 let len2 = insert.len();
-                if pos1 + len1 <= pos2 {
+                if end_of(pos1, len1)? <= pos2 {
                     self.clone().into()
                 } else if pos2 <= pos1 {
-                    Self::new(pos1 + len2, self.what.clone(), Recon::new()).into()
-                } else if pos2 > pos1 && pos2 < pos1 + len1 {
+                    Self::new(end_of(pos1, len2)?, self.what.clone(), self.recon.clone()).into()
+                } else {
+                    // Insert is strictly inside of deleted range
                     let (a, mut b) = self.split(pos2 - pos1);
-                    b.position += len2;
+                    b.position = end_of(b.position, len2)?;
                     Split::new(a, b).into()
-                } else {
-                    unreachable!()
                 }
             }
             Operation::Split(split) => {
-                let a = self.transform(&split.0, Some(cid));
-                let new_second = split.1.transform(&split.0, None);
-                let b = a.transform(&new_second, Some(cid));
-                b
+                let a = self.try_transform(&split.0, cid)?;
+                let new_second = split
+                    .1
+                    .try_transform(&split.0, Some(ConcurrentOrder::This))?;
+                a.try_transform(&new_second, cid)?
             }
-        }
+        })
     }
 
-    pub fn mirror(&self) -> Operation {
-        Insert::new(
+    pub fn try_with_unit(&self, unit: Unit) -> Result<Delete, OtError> {
+        Ok(Delete::new(
             self.position,
-            self.buf()
-                .expect("delete should be reversible for mirroring")
-                .clone(),
-        )
-        .into()
+            match &self.what {
+                Ok(buf) => Ok(buf.clone().try_with_unit(unit)?),
+                Err(len) => Err(*len),
+            },
+            self.recon.try_with_unit(unit)?,
+        ))
+    }
+
+    pub fn try_mirror(&self) -> Result<Operation, OtError> {
+        let buf = self.buf().ok_or(OtError::NonReversible)?;
+        Ok(Insert::new(self.position, buf.clone()).into())
     }
 }
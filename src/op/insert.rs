@@ -1,8 +1,10 @@
-use crate::{recon::Recon, segment::SegmentBuffer, ConcurrentOrder, TextPosition, TextSize};
+use crate::{
+    error::OtError, recon::Recon, segment::SegmentBuffer, ConcurrentOrder, TextPosition, TextSize,
+};
 
-use super::{delete::Delete, Operation};
+use super::{delete::Delete, end_of, Operation};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Insert {
     pub position: TextPosition,
     buffer: SegmentBuffer,
@@ -12,17 +14,33 @@ impl Insert {
         Insert { position, buffer }
     }
 
+    pub fn buffer(&self) -> &SegmentBuffer {
+        &self.buffer
+    }
+
     pub fn apply(&self, buf: &mut SegmentBuffer) {
         buf.splice(self.position..self.position, Some(self.buffer.clone()))
     }
 
-    pub fn cid(&self, other: &Self) -> ConcurrentOrder {
+    /// Length of buffer after applying this operation to buffer of given length
+    pub fn check(&self, len: TextSize) -> Result<TextSize, OtError> {
+        if self.position > len {
+            return Err(OtError::OutOfRange {
+                end: self.position,
+                len,
+            });
+        }
+        end_of(len, self.len())
+    }
+
+    /// Which of two inserts should be shifted, if it can be decided by positions alone
+    pub fn cid(&self, other: &Self) -> Option<ConcurrentOrder> {
         if self.position < other.position {
-            ConcurrentOrder::Other
+            Some(ConcurrentOrder::Other)
         } else if self.position > other.position {
-            ConcurrentOrder::This
+            Some(ConcurrentOrder::This)
         } else {
-            unreachable!()
+            None
         }
     }
 
@@ -30,9 +48,16 @@ impl Insert {
         self.buffer.len()
     }
 
-    pub fn transform(&self, other: &Operation, cid: Option<ConcurrentOrder>) -> Operation {
-        let cid = cid.unwrap();
-        match other {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn try_transform(
+        &self,
+        other: &Operation,
+        cid: Option<ConcurrentOrder>,
+    ) -> Result<Operation, OtError> {
+        Ok(match other {
             Operation::NoOp => self.clone().into(),
             Operation::Delete(delete) => {
                 let pos1 = self.position;
@@ -40,14 +65,13 @@ impl Insert {
                 let len2 = delete.len();
 
                 let str1 = self.buffer.clone();
-                if pos1 >= pos2 + len2 {
+                if pos1 >= end_of(pos2, len2)? {
                     Insert::new(pos1 - len2, str1)
                 } else if pos1 < pos2 {
                     Insert::new(pos1, str1)
-                } else if pos1 >= pos2 && pos1 < pos2 + len2 {
-                    Insert::new(pos2, str1)
                 } else {
-                    unreachable!()
+                    // Insert is inside of deleted range
+                    Insert::new(pos2, str1)
                 }
                 .into()
             }
@@ -57,26 +81,119 @@ impl Insert {
 
                 let str1 = self.buffer.clone();
 
+                // Identical inserts produce the same text in any order
+                let cid = match cid {
+                    Some(cid) => cid,
+                    None if pos1 != pos2 || self.buffer == other.buffer => ConcurrentOrder::Other,
+                    None => return Err(OtError::AmbiguousOrder),
+                };
+
                 if pos1 < pos2 || (pos1 == pos2 && cid == ConcurrentOrder::Other) {
                     Insert::new(pos1, str1)
-                } else if pos1 > pos2 || (pos1 == pos2 && cid == ConcurrentOrder::This) {
-                    let str2 = other.buffer.clone();
-                    Insert::new(pos1 + str2.len(), str1)
                 } else {
-                    unreachable!()
+                    let str2 = other.buffer.clone();
+                    Insert::new(end_of(pos1, str2.len())?, str1)
                 }
                 .into()
             }
             Operation::Split(split) => {
-                let a = self.transform(&split.0, Some(cid));
-                let new_second = split.1.transform(&split.0, None);
-                let b = a.transform(&new_second, Some(cid));
-                b
+                let a = self.try_transform(&split.0, cid)?;
+                let new_second = split
+                    .1
+                    .try_transform(&split.0, Some(ConcurrentOrder::This))?;
+                a.try_transform(&new_second, cid)?
             }
-        }
+        })
     }
 
     pub fn mirror(&self) -> Operation {
         Delete::reversible(self.position, self.buffer.clone(), Recon::new()).into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::OtError,
+        op::{Delete, Insert, Operation, Split},
+        recon::Recon,
+        segment::SegmentBuffer,
+        ConcurrentOrder,
+    };
+
+    fn insert(position: usize, user: u16, text: &[u8]) -> Operation {
+        Insert::new(position, SegmentBuffer::from_bytes(user, text)).into()
+    }
+
+    fn converge(a: &Operation, b: &Operation, cid: Option<ConcurrentOrder>) -> SegmentBuffer {
+        let initial = SegmentBuffer::from_bytes(0, b"0123");
+        let opposite = cid.map(|cid| match cid {
+            ConcurrentOrder::This => ConcurrentOrder::Other,
+            ConcurrentOrder::Other => ConcurrentOrder::This,
+        });
+
+        let mut ab = initial.clone();
+        a.apply(&mut ab);
+        b.transform(a, opposite).apply(&mut ab);
+
+        let mut ba = initial;
+        b.apply(&mut ba);
+        a.transform(b, cid).apply(&mut ba);
+
+        assert_eq!(ab, ba);
+        ab
+    }
+
+    #[test]
+    fn tie_by_cid() {
+        let a = insert(2, 1, b"b");
+        let b = insert(2, 2, b"a");
+        assert_eq!(
+            a.try_transform(&b, None).err(),
+            Some(OtError::AmbiguousOrder)
+        );
+
+        let mut expected = SegmentBuffer::from_bytes(0, b"01");
+        expected.splice(2..2, Some(SegmentBuffer::from_bytes(2, b"a")));
+        expected.splice(3..3, Some(SegmentBuffer::from_bytes(1, b"b")));
+        expected.splice(4..4, Some(SegmentBuffer::from_bytes(0, b"23")));
+        assert_eq!(converge(&a, &b, Some(ConcurrentOrder::This)), expected);
+    }
+
+    #[test]
+    fn identical() {
+        let a = insert(1, 1, b"x");
+        converge(&a, &a.clone(), None);
+    }
+
+    #[test]
+    fn split_of_inserts_at_same_position() {
+        let split: Operation = Split::new(insert(1, 1, b"a"), insert(1, 1, b"b")).into();
+        let mut buf = SegmentBuffer::from_bytes(0, b"0123");
+        split.apply(&mut buf);
+        let mut expected = SegmentBuffer::from_bytes(0, b"0");
+        expected.splice(1..1, Some(SegmentBuffer::from_bytes(1, b"ab")));
+        expected.splice(3..3, Some(SegmentBuffer::from_bytes(0, b"123")));
+        assert_eq!(buf, expected);
+
+        converge(&split, &insert(1, 2, b"c"), Some(ConcurrentOrder::This));
+    }
+
+    #[test]
+    fn overflowing_transform() {
+        let far = insert(usize::MAX, 1, b"x");
+        assert!(matches!(
+            far.try_transform(&insert(0, 2, b"y"), None),
+            Err(OtError::OutOfRange { .. })
+        ));
+        let delete: Operation = Delete::new(1, Err(usize::MAX), Recon::new()).into();
+        assert!(matches!(
+            insert(2, 1, b"x").try_transform(&delete, None),
+            Err(OtError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            delete.try_transform(&far, None),
+            Err(OtError::OutOfRange { .. })
+        ));
+    }
+}
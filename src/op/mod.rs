@@ -1,10 +1,21 @@
 mod delete;
 mod insert;
 mod split;
-use self::{delete::Delete, insert::Insert, split::Split};
-use crate::{segment::SegmentBuffer, ConcurrentOrder};
+pub use self::{delete::Delete, insert::Insert, split::Split};
+use crate::{
+    error::OtError, segment::SegmentBuffer, unit::Unit, ConcurrentOrder, TextPosition, TextSize,
+};
 
-#[derive(Clone)]
+/// End of text of given length at position, text past [`TextPosition::MAX`] doesn't
+/// fit into any buffer, which length is unknown here
+pub(crate) fn end_of(position: TextPosition, len: TextSize) -> Result<TextPosition, OtError> {
+    position.checked_add(len).ok_or(OtError::OutOfRange {
+        end: TextPosition::MAX,
+        len: TextSize::MAX,
+    })
+}
+
+#[derive(Clone, Debug)]
 pub enum Operation {
     NoOp,
     Delete(Delete),
@@ -13,14 +24,33 @@ pub enum Operation {
 }
 
 impl Operation {
+    /// # Panics
+    ///
+    /// If operation can't be transformed, see [`Operation::try_transform`]
     pub fn transform(&self, other: &Operation, cid: Option<ConcurrentOrder>) -> Operation {
+        self.try_transform(other, cid)
+            .unwrap_or_else(|e| panic!("transform: {}", e))
+    }
+    /// Fails if deleted text of other delete is required, but not known
+    pub fn try_transform(
+        &self,
+        other: &Operation,
+        cid: Option<ConcurrentOrder>,
+    ) -> Result<Operation, OtError> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::trace_span!("transform", operation = ?self, other = ?other, cid = ?cid)
+                .entered();
         match self {
-            Operation::NoOp => Operation::NoOp,
-            Operation::Delete(delete) => delete.transform(other, cid),
-            Operation::Insert(insert) => insert.transform(other, cid),
-            Operation::Split(split) => split.transform(other, cid),
+            Operation::NoOp => Ok(Operation::NoOp),
+            Operation::Delete(delete) => delete.try_transform(other, cid),
+            Operation::Insert(insert) => insert.try_transform(other, cid),
+            Operation::Split(split) => split.try_transform(other, cid),
         }
     }
+    /// # Panics
+    ///
+    /// If operation doesn't fit into buffer, see [`Operation::try_apply`]
     pub fn apply(&self, buf: &mut SegmentBuffer) {
         match self {
             Operation::NoOp => {}
@@ -29,12 +59,128 @@ impl Operation {
             Operation::Split(split) => split.apply(buf),
         }
     }
+    /// Applies operation, buffer is left unchanged on error
+    pub fn try_apply(&self, buf: &mut SegmentBuffer) -> Result<(), OtError> {
+        self.check(buf.len())?;
+        self.apply(buf);
+        Ok(())
+    }
+    /// Length of buffer after applying this operation to buffer of given length
+    pub fn check(&self, len: TextSize) -> Result<TextSize, OtError> {
+        match self {
+            Operation::NoOp => Ok(len),
+            Operation::Delete(delete) => delete.check(len),
+            Operation::Insert(insert) => insert.check(len),
+            Operation::Split(split) => split.check(len),
+        }
+    }
+    /// Fails if operation points inside of code point of buffer, which it should be
+    /// applied to
+    pub fn check_positions(&self, buf: &SegmentBuffer) -> Result<(), OtError> {
+        match self {
+            Operation::NoOp => Ok(()),
+            Operation::Delete(delete) => {
+                buf.check_position(delete.position)?;
+                buf.check_position(end_of(delete.position, delete.len())?)
+            }
+            Operation::Insert(insert) => buf.check_position(insert.position),
+            // Both parts are positioned in the same buffer
+            Operation::Split(split) => {
+                split.0.check_positions(buf)?;
+                split.1.check_positions(buf)
+            }
+        }
+    }
+    /// Fails if text carried by reversible delete, which undo restores, is not the text
+    /// of buffer, which it should be applied to
+    pub(crate) fn check_deleted(&self, buf: &SegmentBuffer) -> Result<(), OtError> {
+        match self {
+            Operation::Delete(Delete {
+                position,
+                what: Ok(what),
+                ..
+            }) => {
+                let range = *position..end_of(*position, what.len())?;
+                match buf.try_slice(range)?.same_text(what) {
+                    true => Ok(()),
+                    false => Err(OtError::DeletedTextMismatch),
+                }
+            }
+            Operation::NoOp | Operation::Delete(_) | Operation::Insert(_) => Ok(()),
+            // Both parts are positioned in the same buffer
+            Operation::Split(split) => {
+                split.0.check_deleted(buf)?;
+                split.1.check_deleted(buf)
+            }
+        }
+    }
+    /// Adds lengths of inserted and deleted text
+    pub(crate) fn count_changes(&self, inserted: &mut TextSize, deleted: &mut TextSize) {
+        match self {
+            Operation::NoOp => {}
+            Operation::Delete(delete) => *deleted = deleted.saturating_add(delete.len()),
+            Operation::Insert(insert) => *inserted = inserted.saturating_add(insert.len()),
+            Operation::Split(split) => {
+                split.0.count_changes(inserted, deleted);
+                split.1.count_changes(inserted, deleted);
+            }
+        }
+    }
+    /// Only concurrent inserts may need a concurrency id to be transformed
+    pub fn requires_cid(&self) -> bool {
+        match self {
+            Operation::Insert(_) => true,
+            Operation::Split(split) => split.0.requires_cid() || split.1.requires_cid(),
+            Operation::NoOp | Operation::Delete(_) => false,
+        }
+    }
+    pub fn cid(&self, other: &Operation) -> Option<ConcurrentOrder> {
+        match (self, other) {
+            (Operation::Insert(insert), Operation::Insert(other)) => insert.cid(other),
+            _ => None,
+        }
+    }
+    /// # Panics
+    ///
+    /// If operation contains non-reversible delete, see [`Operation::try_mirror`]
     pub fn mirror(&self) -> Operation {
+        self.try_mirror()
+            .unwrap_or_else(|e| panic!("mirror: {}", e))
+    }
+    pub fn try_mirror(&self) -> Result<Operation, OtError> {
         match self {
+            Operation::NoOp => Ok(Operation::NoOp),
+            Operation::Delete(delete) => delete.try_mirror(),
+            Operation::Insert(insert) => Ok(insert.mirror()),
+            Operation::Split(split) => split.try_mirror(),
+        }
+    }
+
+    /// Same operation with text measured in unit, see [`SegmentBuffer::try_with_unit`]
+    pub fn try_with_unit(&self, unit: Unit) -> Result<Operation, OtError> {
+        Ok(match self {
             Operation::NoOp => Operation::NoOp,
-            Operation::Delete(delete) => delete.mirror(),
-            Operation::Insert(insert) => insert.mirror(),
-            Operation::Split(split) => split.mirror(),
+            Operation::Delete(delete) => delete.try_with_unit(unit)?.into(),
+            Operation::Insert(insert) => Insert::new(
+                insert.position,
+                insert.buffer().clone().try_with_unit(unit)?,
+            )
+            .into(),
+            Operation::Split(split) => {
+                Split::new(split.0.try_with_unit(unit)?, split.1.try_with_unit(unit)?).into()
+            }
+        })
+    }
+    /// Whether all text of operation is already measured in unit
+    pub(crate) fn is_in(&self, unit: Unit) -> bool {
+        match self {
+            Operation::NoOp => true,
+            Operation::Delete(delete) => {
+                delete.buf().is_none_or(|buf| buf.unit() == unit)
+                    && delete.recon.iter().all(|s| s.buffer.unit() == unit)
+            }
+            Operation::Insert(insert) => insert.buffer().unit() == unit,
+            Operation::Split(split) => split.0.is_in(unit) && split.1.is_in(unit),
         }
     }
 }
@@ -54,3 +200,189 @@ impl From<Split> for Operation {
         Operation::Split(Box::new(s))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Delete, Insert, Operation, Split};
+    use crate::{error::OtError, recon::Recon, segment::SegmentBuffer, ConcurrentOrder, State};
+    use proptest::prelude::*;
+
+    fn buffer() -> impl Strategy<Value = SegmentBuffer> {
+        prop::collection::vec((0u16..3, "[a-d]{1,4}"), 0..5).prop_map(|segments| {
+            let mut buf = SegmentBuffer::default();
+            for (user, text) in segments {
+                buf.splice(
+                    buf.len()..buf.len(),
+                    Some(SegmentBuffer::from_bytes(user, text.as_bytes())),
+                );
+            }
+            buf
+        })
+    }
+
+    fn insert(len: usize) -> impl Strategy<Value = Operation> {
+        (0..=len, 1u16..4, "[x-z]{1,3}").prop_map(|(pos, user, text)| {
+            Insert::new(pos, SegmentBuffer::from_bytes(user, text.as_bytes())).into()
+        })
+    }
+
+    /// Reversible delete of buffer text
+    fn delete(buf: SegmentBuffer) -> impl Strategy<Value = Delete> {
+        (0..=buf.len())
+            .prop_flat_map(move |pos| (Just(pos), 0..=buf.len() - pos, Just(buf.clone())))
+            .prop_map(|(pos, len, buf)| {
+                Delete::reversible(pos, buf.slice(pos..pos + len), Recon::new())
+            })
+    }
+
+    /// Two deletes, both positioned in the original buffer, as produced by delete
+    /// transformed against insert
+    fn split(buf: SegmentBuffer) -> impl Strategy<Value = Operation> {
+        (0..=buf.len(), 0..=buf.len(), 0..=buf.len(), 0..=buf.len()).prop_map(move |bounds| {
+            let mut bounds = [bounds.0, bounds.1, bounds.2, bounds.3];
+            bounds.sort_unstable();
+            let delete = |range: std::ops::Range<usize>| {
+                Delete::reversible(range.start, buf.slice(range), Recon::new())
+            };
+            Split::new(delete(bounds[0]..bounds[1]), delete(bounds[2]..bounds[3])).into()
+        })
+    }
+
+    fn operation(buf: SegmentBuffer) -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            insert(buf.len()),
+            delete(buf.clone()).prop_map(Operation::from),
+            split(buf),
+        ]
+    }
+
+    /// Buffer with `n` operations, which are concurrent at it
+    fn concurrent(n: usize) -> impl Strategy<Value = (SegmentBuffer, Vec<Operation>)> {
+        buffer().prop_flat_map(move |buf| {
+            (
+                Just(buf.clone()),
+                prop::collection::vec(operation(buf), n..=n),
+            )
+        })
+    }
+
+    fn applied(buf: &SegmentBuffer, ops: &[&Operation]) -> SegmentBuffer {
+        let mut buf = buf.clone();
+        for op in ops {
+            op.apply(&mut buf);
+        }
+        buf.compact();
+        buf
+    }
+
+    fn opposite(cid: Option<ConcurrentOrder>) -> Option<ConcurrentOrder> {
+        cid.map(|cid| match cid {
+            ConcurrentOrder::This => ConcurrentOrder::Other,
+            ConcurrentOrder::Other => ConcurrentOrder::This,
+        })
+    }
+
+    /// Like translation does, ties are decided by positions in the state, where both
+    /// operations were issued, then by sessions, `a` is issued by lower one
+    fn cid(a: &Operation, b: &Operation) -> Option<ConcurrentOrder> {
+        a.cid(b).or(Some(ConcurrentOrder::This))
+    }
+
+    fn transform_pair(a: &Operation, b: &Operation) -> (Operation, Operation) {
+        let cid = cid(a, b);
+        (a.transform(b, cid), b.transform(a, opposite(cid)))
+    }
+
+    proptest! {
+        #[test]
+        fn tp1((buf, ops) in concurrent(2)) {
+            let (a, b) = (&ops[0], &ops[1]);
+            let (a_b, b_a) = transform_pair(a, b);
+            prop_assert_eq!(applied(&buf, &[a, &b_a]), applied(&buf, &[b, &a_b]));
+        }
+
+        #[test]
+        fn tp2((buf, ops) in concurrent(3)) {
+            let (a, b, c) = (&ops[0], &ops[1], &ops[2]);
+            let (a_b, b_a) = transform_pair(a, b);
+            let cid_ca = opposite(cid(a, c));
+            let cid_cb = opposite(cid(b, c));
+            let c_ab = c.transform(a, cid_ca).transform(&b_a, cid_cb);
+            let c_ba = c.transform(b, cid_cb).transform(&a_b, cid_ca);
+            prop_assert_eq!(
+                applied(&buf, &[a, &b_a, &c_ab]),
+                applied(&buf, &[b, &a_b, &c_ba])
+            );
+        }
+
+        #[test]
+        fn mirror_reverts_apply((buf, ops) in concurrent(2)) {
+            let (a, b) = (&ops[0], &ops[1]);
+            prop_assert_eq!(applied(&buf, &[a, &a.mirror()]), applied(&buf, &[]));
+
+            // Deletes transformed against deletes only revert text they removed
+            let (a_b, _) = transform_pair(a, b);
+            prop_assert_eq!(applied(&buf, &[b, &a_b, &a_b.mirror()]), applied(&buf, &[b]));
+        }
+
+        #[test]
+        fn recon_restores_deleted_text(
+            (buf, a, b) in buffer().prop_flat_map(|buf| (Just(buf.clone()), delete(buf.clone()), delete(buf)))
+        ) {
+            let nonreversible = Delete::new(a.position, Err(a.len()), Recon::new());
+            let transformed = match nonreversible.try_transform(&b.clone().into(), None).unwrap() {
+                Operation::Delete(delete) => delete,
+                other => panic!("delete transformed into {:?}", other),
+            };
+            let mut after_b = buf;
+            b.apply(&mut after_b);
+            let mut restored =
+                after_b.slice(transformed.position..transformed.position + transformed.len());
+            transformed.recon.restore(&mut restored);
+            restored.compact();
+            let mut expected = a.buf().unwrap().clone();
+            expected.compact();
+            prop_assert_eq!(restored, expected);
+        }
+
+        #[test]
+        fn split_transforms_as_its_parts(
+            (buf, op, split) in buffer().prop_flat_map(|buf| (Just(buf.clone()), operation(buf.clone()), split(buf)))
+        ) {
+            let (first, second) = match &split {
+                Operation::Split(split) => (&split.0, &split.1),
+                _ => unreachable!(),
+            };
+            let second = second.transform(first, Some(ConcurrentOrder::This));
+            let through_split = op.transform(&split, None);
+            let through_parts = op.transform(first, None).transform(&second, None);
+            prop_assert_eq!(
+                applied(&buf, &[&split, &through_split]),
+                applied(&buf, &[first, &second, &through_parts])
+            );
+        }
+    }
+
+    #[test]
+    fn split_out_of_range() {
+        let mut buf = SegmentBuffer::from_bytes(1, b"abc");
+        let split = Split::new(
+            Delete::new(0, Err(1), Recon::new()),
+            Delete::new(2, Err(2), Recon::new()),
+        );
+        assert_eq!(
+            split.try_apply(&mut buf),
+            Err(OtError::OutOfRange { end: 3, len: 2 })
+        );
+        assert_eq!(buf, SegmentBuffer::from_bytes(1, b"abc"));
+        assert_eq!(
+            Delete::new(0, Err(1), Recon::new())
+                .make_reversible(
+                    &Insert::new(0, buf.clone()).into(),
+                    &State::new(buf.clone())
+                )
+                .err(),
+            Some(OtError::UnexpectedInsert)
+        );
+    }
+}
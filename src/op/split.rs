@@ -1,35 +1,60 @@
-use crate::{segment::SegmentBuffer, ConcurrentOrder};
+use crate::{error::OtError, segment::SegmentBuffer, ConcurrentOrder, TextSize};
 
 use super::Operation;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Split(pub Operation, pub Operation);
 impl Split {
     pub fn new(a: impl Into<Operation>, b: impl Into<Operation>) -> Self {
         Self(a.into(), b.into())
     }
 
+    /// Second part is always located after the first one, so it is shifted on tie
+    fn second_after_first(&self) -> Result<Operation, OtError> {
+        self.1.try_transform(&self.0, Some(ConcurrentOrder::This))
+    }
+
+    /// # Panics
+    ///
+    /// If operation doesn't fit into buffer, see [`Split::try_apply`]
     pub fn apply(&self, buf: &mut SegmentBuffer) {
+        self.try_apply(buf)
+            .unwrap_or_else(|e| panic!("apply: {}", e))
+    }
+
+    /// Applies both parts, buffer is left unchanged on error
+    pub fn try_apply(&self, buf: &mut SegmentBuffer) -> Result<(), OtError> {
+        let second = self.second_after_first()?;
+        second.check(self.0.check(buf.len())?)?;
         self.0.apply(buf);
-        let transformed_second = self.0.transform(&self.0, None);
-        transformed_second.apply(buf);
+        second.apply(buf);
+        Ok(())
+    }
+
+    /// Length of buffer after applying both parts to buffer of given length
+    pub fn check(&self, len: TextSize) -> Result<TextSize, OtError> {
+        let len = self.0.check(len)?;
+        self.second_after_first()?.check(len)
     }
 
-    pub fn transform(&self, other: &Operation, cid: Option<ConcurrentOrder>) -> Operation {
-        if let Some(cid) = cid {
-            Self(
-                self.0.transform(other, Some(cid)),
-                self.1.transform(other, Some(cid)),
-            )
-        } else {
-            Self(self.0.transform(other, None), self.1.transform(other, None))
-        }
-        .into()
+    pub fn try_transform(
+        &self,
+        other: &Operation,
+        cid: Option<ConcurrentOrder>,
+    ) -> Result<Operation, OtError> {
+        Ok(Self(
+            self.0.try_transform(other, cid)?,
+            self.1.try_transform(other, cid)?,
+        )
+        .into())
     }
 
-    pub fn mirror(&self) -> Operation {
-        let new_second = self.1.transform(&self.0, None);
-        Self(self.0.mirror(), new_second.mirror()).into()
+    pub fn try_mirror(&self) -> Result<Operation, OtError> {
+        Ok(Self(
+            self.0.try_mirror()?,
+            self.second_after_first()?.try_mirror()?,
+        )
+        .into())
     }
 }
 impl<A: Into<Operation>, B: Into<Operation>> From<(A, B)> for Split {
@@ -0,0 +1,489 @@
+//! Order of concurrent inserts, which end up at the same position
+//!
+//! Positions alone can't order inserts, once text between them is deleted
+//! concurrently, and pairwise tie-breaks may disagree between sites, depending on the
+//! path requests were translated by. Instead every inserted character is placed into
+//! a tree, where its parent is the character it was inserted after, siblings are
+//! ordered by [`sibling_order`], and the document is the preorder traversal of the
+//! tree. The tree only depends on requests, so every site orders tied inserts alike.
+//! Undo/redo revives deleted characters at their places in the tree, so text of
+//! concurrent insert may belong inside of revived text, which is split then.
+
+use std::{cmp::Ordering, collections::HashMap, ops::Range};
+
+use crate::{
+    error::OtError,
+    op::{end_of, Insert, Operation, Split},
+    request::Request,
+    segment::SegmentBuffer,
+    vector::StateVector,
+    ConcurrentOrder, SessionId, State, TextPosition, TextSize,
+};
+
+/// Insert request, identified by its issuer and own component of its vector, or
+/// [`None`] for text, which is older than the log
+type Owner = Option<(SessionId, usize)>;
+
+/// Character of the document
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Atom {
+    /// Character of the document at the oldest logged state
+    Base(TextPosition),
+    /// Character inserted by logged or currently translated request
+    Inserted {
+        session: SessionId,
+        n: usize,
+        offset: TextSize,
+    },
+}
+
+impl Atom {
+    fn owner(self) -> (Owner, TextSize) {
+        match self {
+            Atom::Base(position) => (None, position),
+            Atom::Inserted { session, n, offset } => (Some((session, n)), offset),
+        }
+    }
+}
+
+/// Inserted parts of operation with their positions, parts of split share base state
+fn insertions<'o>(operation: &'o Operation, parts: &mut Vec<(TextPosition, &'o SegmentBuffer)>) {
+    match operation {
+        Operation::Insert(insert) => parts.push((insert.position, insert.buffer())),
+        Operation::Split(split) => {
+            insertions(&split.0, parts);
+            insertions(&split.1, parts);
+        }
+        Operation::NoOp | Operation::Delete(_) => {}
+    }
+}
+
+/// Amount of text inserted before given part of operation
+fn offset_of(parts: &[(TextPosition, &SegmentBuffer)], i: usize) -> TextSize {
+    let position = parts[i].0;
+    parts
+        .iter()
+        .enumerate()
+        .filter(|(j, (p, _))| *p < position || (*p == position && *j < i))
+        .map(|(_, (_, buf))| buf.len())
+        .sum()
+}
+
+/// Position of character, which is `offset` characters into text deleted by operation
+fn deleted_position(operation: &Operation, offset: TextSize) -> Option<TextPosition> {
+    let mut parts = Vec::new();
+    deletions(operation, &mut parts);
+    parts.sort_by_key(|(position, _)| *position);
+    let mut offset = offset;
+    for (position, len) in parts {
+        if offset < len {
+            return position.checked_add(offset);
+        }
+        offset -= len;
+    }
+    None
+}
+
+fn deletions(operation: &Operation, parts: &mut Vec<(TextPosition, TextSize)>) {
+    match operation {
+        Operation::Delete(delete) => parts.push((delete.position, delete.len())),
+        Operation::Split(split) => {
+            deletions(&split.0, parts);
+            deletions(&split.1, parts);
+        }
+        Operation::NoOp | Operation::Insert(_) => {}
+    }
+}
+
+/// Where character is found, once operation is excluded from the document
+enum Excluded {
+    /// Character is at given position before operation
+    Before(TextPosition),
+    /// Character is at given offset of text, inserted by operation
+    Inserted(TextSize),
+}
+
+fn exclude(operation: &Operation, position: TextPosition) -> Result<Excluded, OtError> {
+    Ok(match operation {
+        Operation::NoOp => Excluded::Before(position),
+        Operation::Insert(insert) if position < insert.position => Excluded::Before(position),
+        Operation::Insert(insert) => match position - insert.position {
+            offset if offset < insert.len() => Excluded::Inserted(offset),
+            _ => Excluded::Before(position - insert.len()),
+        },
+        Operation::Delete(delete) if position < delete.position => Excluded::Before(position),
+        Operation::Delete(delete) => Excluded::Before(
+            position
+                .checked_add(delete.len())
+                .ok_or(OtError::NoTranslationPath)?,
+        ),
+        Operation::Split(split) => {
+            let second = split
+                .1
+                .try_transform(&split.0, Some(ConcurrentOrder::This))?;
+            match exclude(&second, position)? {
+                Excluded::Before(position) => exclude(&split.0, position)?,
+                inserted => inserted,
+            }
+        }
+    })
+}
+
+/// Number of requests, request is issued after, it only grows along causal chains
+fn time(request: &Request) -> usize {
+    request
+        .vector()
+        .iter()
+        .fold(0, |time, (_, n)| time.saturating_add(*n))
+}
+
+/// Order of siblings in character tree, newer inserts are closer to their parent, as
+/// causally later insert is done right after the parent, ties are resolved by
+/// inserted text and then by session id, text older than the log is the last child
+fn sibling_order(a: Option<&Request>, b: Option<&Request>) -> Ordering {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        (None, Some(_)) => return Ordering::Greater,
+        (Some(_), None) => return Ordering::Less,
+        (None, None) => return Ordering::Equal,
+    };
+    let text = |request: &Request| match request.operation() {
+        Some(Operation::Insert(insert)) => insert
+            .buffer()
+            .chunks()
+            .flat_map(|segment| segment.iter().copied())
+            .collect(),
+        _ => Vec::new(),
+    };
+    time(b)
+        .cmp(&time(a))
+        .then_with(|| text(a).cmp(&text(b)))
+        .then_with(|| b.user().cmp(&a.user()))
+}
+
+/// First index in range, for which predicate doesn't hold, predicate should hold for
+/// all indexes before it
+fn partition_point(
+    range: Range<usize>,
+    mut predicate: impl FnMut(usize) -> Result<bool, OtError>,
+) -> Result<usize, OtError> {
+    let (mut low, mut high) = (range.start, range.end);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match predicate(middle)? {
+            true => low = middle + 1,
+            false => high = middle,
+        }
+    }
+    Ok(low)
+}
+
+/// Runs of ancestors from the tree root down to character, each run is a prefix of
+/// text inserted by single request, which ends at given offset
+type Path = Vec<(Owner, TextSize)>;
+
+/// Paths of characters, inserted by two concurrent requests translated to the same
+/// state, each one is found once
+struct Paths<'a> {
+    state: &'a State,
+    at: &'a StateVector,
+    requests: [&'a Request; 2],
+    /// Associated request of undo/redo, translated to the state it is mirrored at
+    reverted: [Option<(Request, StateVector)>; 2],
+    origins: HashMap<(SessionId, usize), Option<Atom>>,
+    paths: HashMap<(usize, TextSize), Path>,
+}
+
+impl<'a> Paths<'a> {
+    fn new(state: &'a State, at: &'a StateVector, requests: [&'a Request; 2]) -> Self {
+        Paths {
+            state,
+            at,
+            requests,
+            reverted: [None, None],
+            origins: HashMap::new(),
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Character at `offset` of text inserted by one of the requests, undo/redo
+    /// inserts characters, deleted by its associated request
+    fn head(&mut self, side: usize, offset: TextSize) -> Result<Atom, OtError> {
+        let request = self.requests[side];
+        if let Request::Do(_) = request {
+            return Ok(Atom::Inserted {
+                session: request.user(),
+                n: request.vector().get(request.user()),
+                offset,
+            });
+        }
+        if self.reverted[side].is_none() {
+            let (assoc, mirror_at) = self.state.mirrored_at(request, self.at)?;
+            let reverted = self.state.translate_iterative(assoc, &mirror_at)?;
+            self.reverted[side] = Some((reverted, mirror_at));
+        }
+        let (reverted, mirror_at) = self.reverted[side].as_ref().unwrap();
+        let operation = reverted.operation().ok_or(OtError::NotTranslated)?;
+        let position = deleted_position(operation, offset).ok_or(OtError::NoTranslationPath)?;
+        self.state.atom_at(position, mirror_at)
+    }
+
+    fn path(&mut self, atom: Atom) -> Result<Path, OtError> {
+        let mut path = Vec::new();
+        let mut next = Some(atom);
+        while let Some(atom) = next {
+            path.push(atom.owner());
+            next = match atom {
+                Atom::Base(_) => None,
+                Atom::Inserted { session, n, .. } => match self.origins.get(&(session, n)) {
+                    Some(origin) => *origin,
+                    None => {
+                        let origin = self.state.origin(session, n, &self.requests)?;
+                        self.origins.insert((session, n), origin);
+                        origin
+                    }
+                },
+            };
+        }
+        path.reverse();
+        Ok(path)
+    }
+
+    /// Order of characters, given by request and offset into text inserted by it
+    fn compare(&mut self, a: (usize, TextSize), b: (usize, TextSize)) -> Result<Ordering, OtError> {
+        for (side, offset) in [a, b] {
+            if !self.paths.contains_key(&(side, offset)) {
+                let atom = self.head(side, offset)?;
+                let path = self.path(atom)?;
+                self.paths.insert((side, offset), path);
+            }
+        }
+        let (a, b) = (&self.paths[&a], &self.paths[&b]);
+        match self.state.compare_paths(a, b, &self.requests) {
+            Ordering::Equal => Err(OtError::AmbiguousOrder),
+            order => Ok(order),
+        }
+    }
+}
+
+impl State {
+    /// Transforms translated request against concurrent one, when their inserts are
+    /// at the same positions, tied text of both is merged by the order of characters,
+    /// so text revived by undo/redo is split, if the other text belongs inside of it,
+    /// [`None`] if there are no tied inserts, `request` and `other` are untranslated
+    pub(crate) fn merge_insertions(
+        &self,
+        at: &StateVector,
+        (request, translated): (&Request, &Operation),
+        (other, other_translated): (&Request, &Operation),
+    ) -> Result<Option<Operation>, OtError> {
+        let (mut parts, mut other_parts) = (Vec::new(), Vec::new());
+        insertions(translated, &mut parts);
+        insertions(other_translated, &mut other_parts);
+        let tied = |position: TextPosition| {
+            other_parts
+                .iter()
+                .enumerate()
+                .filter(move |(_, (other, _))| *other == position)
+        };
+        if !parts
+            .iter()
+            .any(|(position, _)| tied(*position).next().is_some())
+        {
+            return Ok(None);
+        }
+
+        let mut paths = Paths::new(self, at, [request, other]);
+        let mut merged = Vec::new();
+        for (i, &(position, buffer)) in parts.iter().enumerate() {
+            let before = other_parts
+                .iter()
+                .filter(|(other, _)| *other < position)
+                .map(|(_, buffer)| buffer.len())
+                .sum();
+            let base = end_of(position, before)?;
+            let first = match tied(position).next() {
+                Some((first, _)) => first,
+                None => {
+                    merged.push((base, buffer.clone()));
+                    continue;
+                }
+            };
+            let offset = offset_of(&parts, i);
+            let other_offset = offset_of(&other_parts, first);
+            let other_len = tied(position).map(|(_, (_, buffer))| buffer.len()).sum();
+            // Text is cut into blocks, each one follows the same tied characters of
+            // the other request
+            let mut start = 0;
+            while start < buffer.len() {
+                let preceding = partition_point(0..other_len, |v| {
+                    let order = paths.compare((1, other_offset + v), (0, offset + start))?;
+                    Ok(order == Ordering::Less)
+                })?;
+                let end = match preceding < other_len {
+                    true => partition_point(start + 1..buffer.len(), |t| {
+                        let order =
+                            paths.compare((0, offset + t), (1, other_offset + preceding))?;
+                        Ok(order == Ordering::Less)
+                    })?,
+                    false => buffer.len(),
+                };
+                merged.push((end_of(base, preceding)?, buffer.try_slice(start..end)?));
+                start = end;
+            }
+        }
+
+        // Parts share base, each one is shifted by the ones before it
+        merged.sort_by_key(|(position, _)| *position);
+        let mut merged = merged
+            .into_iter()
+            .rev()
+            .map(|(position, buffer)| Operation::from(Insert::new(position, buffer)));
+        let last = merged.next().ok_or(OtError::NoTranslationPath)?;
+        Ok(Some(
+            merged.fold(last, |rest, part| Split::new(part, rest).into()),
+        ))
+    }
+
+    /// Insert request, which owns characters, looked up among requests being
+    /// translated first, as they may be not logged yet
+    fn owner<'a>(
+        &'a self,
+        session: SessionId,
+        n: usize,
+        known: &[&'a Request],
+    ) -> Option<&'a Request> {
+        known
+            .iter()
+            .copied()
+            .find(|r| r.user() == session && r.vector().get(session) == n)
+            .filter(|r| matches!(r, Request::Do(_)))
+            .or_else(|| self.log.get(session, n))
+    }
+
+    /// Character, which text inserted by request follows, [`None`] if it is inserted
+    /// at the start of the document
+    fn origin(
+        &self,
+        session: SessionId,
+        n: usize,
+        known: &[&Request],
+    ) -> Result<Option<Atom>, OtError> {
+        let request = self
+            .owner(session, n, known)
+            .ok_or(OtError::NoTranslationPath)?;
+        let mut parts = Vec::new();
+        insertions(
+            request.operation().ok_or(OtError::NotTranslated)?,
+            &mut parts,
+        );
+        match parts.iter().map(|(position, _)| *position).min() {
+            None => Err(OtError::NoTranslationPath),
+            Some(0) => Ok(None),
+            Some(position) => self.atom_at(position - 1, request.vector()).map(Some),
+        }
+    }
+
+    /// Preorder of characters, given by their paths
+    fn compare_paths(
+        &self,
+        a: &[(Owner, TextSize)],
+        b: &[(Owner, TextSize)],
+        known: &[&Request],
+    ) -> Ordering {
+        let request = |owner: Owner| owner.and_then(|(session, n)| self.owner(session, n, known));
+        for (i, (&(owner, offset), &(other, other_offset))) in a.iter().zip(b).enumerate() {
+            if owner != other {
+                return sibling_order(request(owner), request(other));
+            }
+            // Path, which leaves the run earlier, continues with a sibling of the
+            // next character of the run
+            match offset.cmp(&other_offset) {
+                Ordering::Equal => {}
+                Ordering::Less => {
+                    return match a.get(i + 1) {
+                        Some((next, _)) => sibling_order(request(*next), request(owner)),
+                        None => Ordering::Less,
+                    }
+                }
+                Ordering::Greater => {
+                    return match b.get(i + 1) {
+                        Some((next, _)) => sibling_order(request(owner), request(*next)),
+                        None => Ordering::Greater,
+                    }
+                }
+            }
+        }
+        // Ancestor goes first
+        a.len().cmp(&b.len())
+    }
+
+    /// Counters of the oldest logged state
+    fn log_start(&self) -> StateVector {
+        let mut start = StateVector::new();
+        for (session, n) in self.vector.iter() {
+            let first = self.log.first(session);
+            start.set(session, first.map_or(*n, |r| r.vector().get(session)));
+        }
+        start
+    }
+
+    /// Character at position of the document at given state, found by undoing
+    /// requests one by one, until the one, which inserted it, or the oldest logged
+    /// state is reached
+    fn atom_at(&self, position: TextPosition, at: &StateVector) -> Result<Atom, OtError> {
+        let start = self.log_start();
+        let mut position = position;
+        let mut vector = at.clone();
+        'walk: loop {
+            let sessions = vector.sessions().collect::<Vec<_>>();
+            for session in sessions {
+                let n = vector.get(session);
+                if n <= start.get(session) {
+                    continue;
+                }
+                let request = self
+                    .log
+                    .get(session, n - 1)
+                    .ok_or(OtError::NoTranslationPath)?;
+                let mut previous = vector.clone();
+                match request {
+                    Request::Do(_) => {
+                        previous.remove(session, 1);
+                        if !self.reachable(&previous) {
+                            continue;
+                        }
+                        let translated = self.translate_iterative(request, &previous)?;
+                        let operation = translated.operation().ok_or(OtError::NotTranslated)?;
+                        position = match exclude(operation, position)? {
+                            Excluded::Before(position) => position,
+                            Excluded::Inserted(offset) => {
+                                return Ok(Atom::Inserted {
+                                    session,
+                                    n: n - 1,
+                                    offset,
+                                })
+                            }
+                        };
+                    }
+                    // Undo/redo cancels its associated request out
+                    Request::Undo(_) | Request::Redo(_) => {
+                        let assoc = self
+                            .associated_request(request)
+                            .ok_or(OtError::NoAssociatedRequest)?;
+                        previous.set(session, assoc.vector().get(session));
+                        if !self.reachable(&previous) {
+                            continue;
+                        }
+                    }
+                }
+                vector = previous;
+                continue 'walk;
+            }
+            return match vector.iter().all(|(session, n)| *n <= start.get(session)) {
+                true => Ok(Atom::Base(position)),
+                false => Err(OtError::NoTranslationPath),
+            };
+        }
+    }
+}
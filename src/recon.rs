@@ -1,4 +1,4 @@
-use crate::{segment::SegmentBuffer, TextPosition};
+use crate::{error::OtError, segment::SegmentBuffer, unit::Unit, TextPosition};
 use std::ops::Deref;
 
 #[derive(Clone, Debug)]
@@ -7,7 +7,7 @@ pub struct ReconSegment {
     pub buffer: SegmentBuffer,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Recon(Vec<ReconSegment>);
 
 impl Deref for Recon {
@@ -28,9 +28,23 @@ impl Recon {
     }
 
     pub fn restore(&self, buf: &mut SegmentBuffer) {
+        self.try_restore(buf)
+            .unwrap_or_else(|e| panic!("restore: {}", e))
+    }
+
+    pub fn try_restore(&self, buf: &mut SegmentBuffer) -> Result<(), OtError> {
+        for segment in self.0.iter() {
+            buf.try_splice(segment.offset..segment.offset, Some(segment.buffer.clone()))?
+        }
+        Ok(())
+    }
+
+    pub fn try_with_unit(&self, unit: Unit) -> Result<Recon, OtError> {
+        let mut recon = Recon::new();
         for segment in self.0.iter() {
-            buf.splice(segment.offset..segment.offset, Some(segment.buffer.clone()))
+            recon.add(segment.offset, segment.buffer.clone().try_with_unit(unit)?);
         }
+        Ok(recon)
     }
 
     pub fn split_at(&self, at: TextPosition) -> (Recon, Recon) {
@@ -1,4 +1,6 @@
-use crate::{op::Operation, vector::StateVector, ConcurrentOrder, SessionId, State};
+use crate::{
+    error::OtError, op::Operation, vector::StateVector, ConcurrentOrder, SessionId, State,
+};
 
 use super::Request;
 
@@ -10,13 +12,33 @@ pub struct DoRequest {
 }
 
 impl DoRequest {
+    pub fn new(user: SessionId, vector: StateVector, operation: Operation) -> Self {
+        DoRequest {
+            user,
+            vector,
+            operation,
+        }
+    }
+
+    pub fn operation(&self) -> &Operation {
+        &self.operation
+    }
+
     pub fn execute(&self, state: &mut State) {
         self.operation.apply(&mut state.buffer);
         state.vector.add(self.user, 1);
     }
 
-    pub fn transform(&self, other: &Self, cid: Option<ConcurrentOrder>) -> Self {
-        let new_operation = self.operation.transform(&other.operation, cid);
+    pub fn try_transform(
+        &self,
+        other: &Self,
+        cid: Option<ConcurrentOrder>,
+    ) -> Result<Self, OtError> {
+        let new_operation = self.operation.try_transform(&other.operation, cid)?;
+        Ok(self.transformed(other, new_operation))
+    }
+
+    pub(crate) fn transformed(&self, other: &Self, operation: Operation) -> Self {
         Self {
             user: self.user,
             vector: {
@@ -24,25 +46,26 @@ impl DoRequest {
                 new_vector.add(other.user, 1);
                 new_vector
             },
-            operation: new_operation,
+            operation,
         }
     }
 
-    pub fn mirror(&self, amount: usize) -> Request {
-        Request::Do(DoRequest {
+    pub fn try_mirror(&self, amount: usize) -> Result<Request, OtError> {
+        Ok(Request::Do(DoRequest {
             user: self.user,
             vector: {
                 let mut new_vector = self.vector.clone();
                 new_vector.add(self.user, amount);
                 new_vector
             },
-            operation: self.operation.mirror(),
-        })
+            operation: self.operation.try_mirror()?,
+        }))
     }
 
-    pub fn fold(&self, user: SessionId, amount: usize) -> Request {
-        assert!(amount % 2 == 0);
-        DoRequest {
+    /// Amount is always even, as every folded request is paired with its undo/redo
+    pub(crate) fn fold(&self, user: SessionId, amount: usize) -> Request {
+        debug_assert!(amount.is_multiple_of(2));
+        Request::Do(DoRequest {
             user: self.user,
             vector: {
                 let mut new_vector = self.vector.clone();
@@ -50,17 +73,18 @@ impl DoRequest {
                 new_vector
             },
             operation: self.operation.clone(),
-        }
+        })
     }
 
-    pub fn make_reversible(&self, translated: &DoRequest, state: &State) -> DoRequest {
+    pub fn make_reversible(
+        &self,
+        translated: &DoRequest,
+        state: &State,
+    ) -> Result<DoRequest, OtError> {
         let mut result = self.clone();
-        match result.operation {
-            Operation::Delete(delete) => {
-                result.operation = delete.make_reversible(&translated.operation, state).into()
-            }
-            _ => {}
+        if let Operation::Delete(delete) = &result.operation {
+            result.operation = delete.make_reversible(&translated.operation, state)?.into()
         }
-        result
+        Ok(result)
     }
 }
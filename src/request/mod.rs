@@ -1,4 +1,7 @@
-use crate::{vector::StateVector, SessionId};
+use crate::{
+    error::OtError, log::RequestLog, op::Operation, unit::Unit, vector::StateVector,
+    ConcurrentOrder, SessionId,
+};
 
 use self::{dor::DoRequest, redo::RedoRequest, undo::UndoRequest};
 
@@ -6,6 +9,7 @@ pub mod dor;
 pub mod redo;
 pub mod undo;
 
+#[derive(Clone)]
 pub enum Request {
     Do(DoRequest),
     Redo(RedoRequest),
@@ -27,24 +31,69 @@ impl Request {
             Request::Undo(undo) => &undo.vector,
         }
     }
-    pub fn associated_request<'r>(&self, log: &'r Vec<Request>) -> Option<&'r Request> {
+    /// Request reverted by undo/redo, do requests have none
+    pub fn associated_request<'r>(&self, log: &'r RequestLog) -> Option<&'r Request> {
         match self {
-            Request::Do(_) => unreachable!(),
-            Request::Redo(redo) => redo.associated_request(self, log),
-            Request::Undo(undo) => undo.associated_request(self, log),
+            Request::Do(_) => None,
+            Request::Redo(redo) => redo.associated_request(log),
+            Request::Undo(undo) => undo.associated_request(log),
         }
     }
-    pub fn mirror(&self, by: usize) -> Request {
+    /// Only translated requests are mirrored, and translation always produces [`DoRequest`]
+    pub fn try_mirror(&self, by: usize) -> Result<Request, OtError> {
         match self {
-            Request::Do(dor) => dor.mirror(by),
-            _ => unreachable!(),
+            Request::Do(dor) => dor.try_mirror(by),
+            _ => Err(OtError::NotTranslated),
         }
     }
-    pub fn fold(&self, session: SessionId, amount: usize) -> Request {
+    pub(crate) fn fold(&self, session: SessionId, amount: usize) -> Request {
         match self {
             Request::Do(dor) => dor.fold(session, amount),
             Request::Redo(redo) => redo.fold(session, amount),
             Request::Undo(undo) => undo.fold(session, amount),
         }
     }
+    /// Only translated requests can be transformed, and translation always produces [`DoRequest`]
+    pub fn try_transform(
+        &self,
+        other: &Request,
+        cid: Option<ConcurrentOrder>,
+    ) -> Result<Request, OtError> {
+        match (self, other) {
+            (Request::Do(dor), Request::Do(other)) => {
+                Ok(Request::Do(dor.try_transform(other, cid)?))
+            }
+            _ => Err(OtError::NotTranslated),
+        }
+    }
+    /// Translated request, which follows other one, with already transformed operation
+    pub(crate) fn transformed(
+        &self,
+        other: &Request,
+        operation: Operation,
+    ) -> Result<Request, OtError> {
+        match (self, other) {
+            (Request::Do(dor), Request::Do(other)) => {
+                Ok(Request::Do(dor.transformed(other, operation)))
+            }
+            _ => Err(OtError::NotTranslated),
+        }
+    }
+    /// Same request with text measured in unit, see [`Operation::try_with_unit`]
+    pub fn try_with_unit(&self, unit: Unit) -> Result<Request, OtError> {
+        Ok(match self {
+            Request::Do(dor) => Request::Do(DoRequest::new(
+                dor.user,
+                dor.vector.clone(),
+                dor.operation().try_with_unit(unit)?,
+            )),
+            request => request.clone(),
+        })
+    }
+    pub fn operation(&self) -> Option<&Operation> {
+        match self {
+            Request::Do(dor) => Some(dor.operation()),
+            _ => None,
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{vector::StateVector, SessionId};
+use crate::{log::RequestLog, vector::StateVector, SessionId};
 
 use super::Request;
 
@@ -9,31 +9,35 @@ pub struct RedoRequest {
 }
 
 impl RedoRequest {
-    pub fn associated_request<'r>(
-        &self,
-        selfr: &Request,
-        log: &'r Vec<Request>,
-    ) -> Option<&'r Request> {
+    pub fn associated_request<'r>(&self, log: &'r RequestLog) -> Option<&'r Request> {
         let mut sequence = 1;
-        let request = log.iter().rev().find(|i| {
-            if i.user() != self.user {
-                return false;
-            }
-            if i.vector().get(self.user) > self.vector.get(self.user) {
-                return false;
-            }
-            match i {
-                Request::Redo(_) => sequence += 1,
-                _ => sequence -= 1,
-            };
+        let request = log
+            .before(self.user, self.vector.get(self.user))
+            .rev()
+            .find(|i| {
+                match i {
+                    Request::Redo(_) => sequence += 1,
+                    _ => sequence -= 1,
+                };
 
-            sequence == 0
-        });
+                sequence == 0
+            });
 
         match request {
             Some(r @ Request::Undo(_)) => Some(r),
-            Some(_) => unreachable!(),
-            None => None,
+            // Last request wasn't undone, nothing to redo
+            Some(_) | None => None,
         }
     }
+
+    pub(crate) fn fold(&self, user: SessionId, amount: usize) -> Request {
+        Request::Redo(RedoRequest {
+            user: self.user,
+            vector: {
+                let mut new_vector = self.vector.clone();
+                new_vector.add(user, amount);
+                new_vector
+            },
+        })
+    }
 }
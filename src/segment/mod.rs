@@ -0,0 +1,931 @@
+use crate::{
+    error::OtError,
+    unit::{is_boundary, Unit},
+    SessionId, TextPosition, TextSize,
+};
+use smallvec::SmallVec;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    iter::FromIterator,
+    ops::{Deref, DerefMut, Range, RangeBounds},
+};
+
+pub mod tree;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Segment(SessionId, SmallVec<[u8; 16]>);
+impl Deref for Segment {
+    type Target = SmallVec<[u8; 16]>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.1
+    }
+}
+impl DerefMut for Segment {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.1
+    }
+}
+impl Segment {
+    pub fn new(user: SessionId, data: &[u8]) -> Self {
+        Segment(user, data.into())
+    }
+
+    #[inline]
+    pub fn user(&self) -> SessionId {
+        self.0
+    }
+
+    pub fn len(&self) -> TextSize {
+        self.1.len() as TextSize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.1.is_empty()
+    }
+}
+
+/// How segments of [`SegmentBuffer`] are stored, all offsets here are in bytes, and are
+/// checked by buffer
+pub trait Storage: Clone + Default + fmt::Debug + PartialEq + Eq + FromIterator<Segment> {
+    /// Segments in order, storage may merge adjacent segments of the same user
+    fn runs(&self) -> impl Iterator<Item = Cow<'_, Segment>>;
+    /// Segments as they are stored, adjacent ones may have the same user
+    fn chunks(&self) -> impl Iterator<Item = &Segment>;
+    fn len_in(&self, unit: Unit) -> TextSize;
+    /// Segment which contains unit position, position inside of it and byte offset of
+    /// segment, if position is not before the end - `None`, rest of position and length
+    fn find(&self, unit: Unit, pos: TextPosition) -> (Option<&Segment>, TextPosition, usize);
+    /// Length of the first `bytes` bytes in unit
+    fn measure(&self, unit: Unit, bytes: usize) -> TextSize;
+    fn slice(&self, start: usize, end: usize) -> Self;
+    /// Replaces range with inserted segments, and merges adjacent segments of the same user
+    fn splice(&mut self, start: usize, end: usize, insert: Option<Self>);
+    /// Length of text written by every session, in unit
+    fn contribution(&self, unit: Unit) -> BTreeMap<SessionId, TextSize>;
+    fn compact(&mut self);
+    fn is_empty(&self) -> bool;
+}
+
+/// Segments in vector, every edit walks and shifts all of them, which is fastest for
+/// small buffers, i.e operations
+// Can be replaced with Vec<u8> and segments to (UserId, Range<usize>), instead of keeping every buffer inside of segment,
+// But it only would be faster for compaction, inserts would be slower
+pub type Flat = SmallVec<[Segment; 1]>;
+
+impl Storage for Flat {
+    fn runs(&self) -> impl Iterator<Item = Cow<'_, Segment>> {
+        self.iter().map(Cow::Borrowed)
+    }
+    fn chunks(&self) -> impl Iterator<Item = &Segment> {
+        self.as_slice().iter()
+    }
+    fn len_in(&self, unit: Unit) -> TextSize {
+        self.iter().map(|s| unit.len(s)).sum()
+    }
+    fn find(&self, unit: Unit, pos: TextPosition) -> (Option<&Segment>, TextPosition, usize) {
+        let (mut rest, mut offset) = (pos, 0);
+        for segment in self.iter() {
+            let len = unit.len(segment);
+            if rest < len {
+                return (Some(segment), rest, offset);
+            }
+            rest -= len;
+            offset += segment.len();
+        }
+        (None, rest, offset)
+    }
+    fn measure(&self, unit: Unit, mut bytes: usize) -> TextSize {
+        let mut out = 0;
+        for segment in self.iter() {
+            if bytes <= segment.len() {
+                return out + unit.len(&segment[..bytes]);
+            }
+            bytes -= segment.len();
+            out += unit.len(segment);
+        }
+        out
+    }
+    fn slice(&self, mut start: usize, mut end: usize) -> Self {
+        let mut segments = SmallVec::new();
+        for segment in self.iter() {
+            if start < segment.len() && start < end {
+                let end = segment.len().min(end);
+                segments.push(Segment(segment.user(), segment[start..end].into()));
+            }
+            start = start.saturating_sub(segment.len());
+            end = end.saturating_sub(segment.len());
+            if end == 0 {
+                break;
+            }
+        }
+        segments
+    }
+    fn splice(&mut self, mut start: usize, mut end: usize, insert: Option<Self>) {
+        let mut insert_at = None;
+        let mut segment_idx = 0;
+        while segment_idx < self.len() {
+            let segment_length = self[segment_idx].len();
+            if start < segment_length {
+                let removed = start..end.min(segment_length);
+                if start == 0 {
+                    // Beginning of segment
+                    // abcdefg
+                    // ^
+                    if removed.end < segment_length {
+                        // Start of segment
+                        // abcdefg
+                        // ^-^
+                        if insert_at.is_none() {
+                            insert_at = Some(segment_idx);
+                        }
+                        let old_segment = &self[segment_idx];
+                        let new_segment =
+                            Segment(old_segment.user(), old_segment[removed.end..].into());
+                        self[segment_idx] = new_segment;
+                    } else {
+                        // Full segment
+                        // abcdefg
+                        // ^-----^
+                        self.remove(segment_idx);
+                        if insert_at.is_none() {
+                            insert_at = Some(segment_idx);
+                        }
+                        // Next segment is now at the same index
+                        end -= segment_length;
+                        continue;
+                    }
+                } else {
+                    // Inside of segment
+                    // abcdefg
+                    //   ^
+                    if insert_at.is_none() {
+                        insert_at = Some(segment_idx + 1);
+                    }
+                    if removed.end < segment_length {
+                        // Part of segment
+                        // abcdefg
+                        //   ^-^
+                        let old_segment = &mut self[segment_idx];
+                        let new_segment =
+                            Segment(old_segment.user(), old_segment[removed.end..].into());
+                        old_segment.truncate(removed.start);
+                        self.insert(segment_idx + 1, new_segment);
+                        segment_idx += 1;
+                    } else {
+                        // End of segment
+                        // abcdefg
+                        //   ^---^
+                        self[segment_idx].truncate(removed.start);
+                    }
+                }
+            }
+            if start < segment_length && end == start {
+                if insert_at.is_none() {
+                    insert_at = Some(segment_idx);
+                }
+                break;
+            }
+            end = end.saturating_sub(segment_length);
+            start = start.saturating_sub(segment_length);
+            segment_idx += 1;
+        }
+        if let Some(insert) = insert {
+            let insert_at = insert_at.unwrap_or(self.len());
+            self.insert_many(insert_at, insert);
+        }
+        self.compact();
+    }
+    fn contribution(&self, unit: Unit) -> BTreeMap<SessionId, TextSize> {
+        let mut out = BTreeMap::new();
+        for segment in self.iter() {
+            *out.entry(segment.user()).or_insert(0) += unit.len(segment);
+        }
+        out
+    }
+    fn compact(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        let mut last_segment = 0;
+        let mut removed = Vec::new();
+        loop {
+            let (first, rest) = self.split_at_mut(last_segment + 1);
+            if rest.is_empty() {
+                break;
+            }
+            let first = &mut first[first.len() - 1];
+
+            let end = rest
+                .iter()
+                .enumerate()
+                .find(|(_, s)| s.user() != first.user())
+                .map(|(i, _)| i)
+                .unwrap_or(rest.len());
+
+            let range = 0..end;
+            removed.push(last_segment + 1..end + last_segment + 1);
+
+            let to_move = rest[range.clone()].iter().map(|s| s.len()).sum();
+            first.reserve_exact(to_move);
+
+            for segment in rest[range].iter_mut() {
+                first.extend(segment.drain(..));
+            }
+
+            last_segment += end + 1;
+
+            if self.len() == last_segment {
+                break;
+            }
+        }
+        for range in removed.into_iter().rev() {
+            self.drain(range);
+        }
+    }
+    fn is_empty(&self) -> bool {
+        SmallVec::is_empty(self)
+    }
+}
+
+/// Storage used by [`SegmentBuffer`] by default, [`tree::SegmentTree`] with `rope` feature
+#[cfg(not(feature = "rope"))]
+pub type DefaultStorage = Flat;
+/// Storage used by [`SegmentBuffer`] by default, [`Flat`] without `rope` feature
+#[cfg(feature = "rope")]
+pub type DefaultStorage = tree::SegmentTree;
+
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct SegmentBuffer<S = DefaultStorage> {
+    segments: S,
+    /// In units of buffer
+    len: TextSize,
+    unit: Unit,
+}
+impl SegmentBuffer {
+    pub fn new(segments: SmallVec<[Segment; 1]>) -> Self {
+        Self::from_segments(segments)
+    }
+    /// Buffer with all data written by single user
+    pub fn from_bytes(user: SessionId, data: &[u8]) -> Self {
+        if data.is_empty() {
+            return Self::default();
+        }
+        Self::new(smallvec::smallvec![Segment::new(user, data)])
+    }
+    /// Buffer with all text written by single user, positions are counted in unit
+    pub fn from_text(user: SessionId, text: &str, unit: Unit) -> Self {
+        Self {
+            len: unit.len(text.as_bytes()),
+            unit,
+            ..Self::from_bytes(user, text.as_bytes())
+        }
+    }
+}
+impl<S: Storage> SegmentBuffer<S> {
+    /// Buffer with positions counted in bytes, in storage of choice
+    pub fn from_segments(segments: impl IntoIterator<Item = Segment>) -> Self {
+        let segments = S::from_iter(segments);
+        Self {
+            len: segments.len_in(Unit::Bytes),
+            segments,
+            unit: Unit::Bytes,
+        }
+    }
+    /// Same buffer with positions counted in another unit, fails if text can't be
+    /// measured in it
+    pub fn try_with_unit(mut self, unit: Unit) -> Result<Self, OtError> {
+        if unit != self.unit {
+            for segment in self.segments.runs() {
+                unit.validate(&segment)?;
+            }
+            self.len = self.len_in(unit);
+            self.unit = unit;
+        }
+        Ok(self)
+    }
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
+    pub fn len_in(&self, unit: Unit) -> TextSize {
+        if unit == self.unit {
+            return self.len;
+        }
+        self.segments.len_in(unit)
+    }
+    /// Converts position between units
+    pub fn convert(
+        &self,
+        pos: TextPosition,
+        from: Unit,
+        to: Unit,
+    ) -> Result<TextPosition, OtError> {
+        let offset = self.offset(from, pos)?;
+        Ok(self.segments.measure(to, offset))
+    }
+    /// Fails if position is out of buffer or inside of code point
+    pub fn check_position(&self, pos: TextPosition) -> Result<(), OtError> {
+        self.offset(self.unit, pos).map(|_| ())
+    }
+    /// Byte offset of position, segment boundaries are always code point boundaries
+    fn offset(&self, unit: Unit, pos: TextPosition) -> Result<usize, OtError> {
+        if unit == Unit::Bytes && self.unit == Unit::Bytes && pos <= self.len {
+            return Ok(pos);
+        }
+        match self.segments.find(unit, pos) {
+            (Some(segment), rest, offset) => unit
+                .offset(segment, rest)
+                .ok()
+                // Byte offsets may point inside of UTF-8 text
+                .filter(|inner| self.unit == Unit::Bytes || is_boundary(segment, *inner))
+                .map(|inner| offset + inner)
+                .ok_or(OtError::InsideCodePoint(pos)),
+            (None, 0, offset) => Ok(offset),
+            (None, _, _) => Err(OtError::OutOfRange {
+                end: pos,
+                len: self.len_in(unit),
+            }),
+        }
+    }
+    pub fn compact(&mut self) {
+        self.segments.compact()
+    }
+    /// # Panics
+    ///
+    /// If range is out of buffer, see [`SegmentBuffer::try_slice`]
+    pub fn slice(&self, range: impl RangeBounds<TextPosition>) -> Self {
+        self.try_slice(range)
+            .unwrap_or_else(|e| panic!("slice: {}", e))
+    }
+    pub fn try_slice(&self, range: impl RangeBounds<TextPosition>) -> Result<Self, OtError> {
+        let Range { start, end } = self.bounds(range);
+        self.check_range(start, end)?;
+        Ok(Self {
+            segments: self
+                .segments
+                .slice(self.offset(self.unit, start)?, self.offset(self.unit, end)?),
+            len: end - start,
+            unit: self.unit,
+        })
+    }
+
+    fn bounds(&self, range: impl RangeBounds<TextPosition>) -> Range<TextPosition> {
+        let start = match range.start_bound() {
+            std::ops::Bound::Included(i) => *i,
+            std::ops::Bound::Excluded(_) => unreachable!(),
+            std::ops::Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            std::ops::Bound::Included(i) => i.saturating_add(1),
+            std::ops::Bound::Excluded(i) => *i,
+            std::ops::Bound::Unbounded => self.len(),
+        };
+        start..end
+    }
+
+    fn check_range(&self, start: TextPosition, end: TextPosition) -> Result<(), OtError> {
+        if end > self.len() || start > end {
+            return Err(OtError::OutOfRange {
+                end: end.max(start),
+                len: self.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// # Panics
+    ///
+    /// If range is out of buffer, see [`SegmentBuffer::try_splice`]
+    pub fn splice(&mut self, range: impl RangeBounds<usize>, insert: Option<Self>) {
+        self.try_splice(range, insert)
+            .unwrap_or_else(|e| panic!("splice: {}", e))
+    }
+    pub fn try_splice(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        insert: Option<Self>,
+    ) -> Result<(), OtError> {
+        let Range { start, end } = self.bounds(range);
+        self.check_range(start, end)?;
+        let insert = insert
+            .map(|insert| insert.try_with_unit(self.unit))
+            .transpose()?;
+        let len = self.len - (end - start) + insert.as_ref().map_or(0, |insert| insert.len);
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "splice",
+            start,
+            end,
+            inserted = insert.as_ref().map_or(0, |insert| insert.len),
+            len = self.len,
+            unit = %self.unit,
+        )
+        .entered();
+        let start = self.offset(self.unit, start)?;
+        let end = self.offset(self.unit, end)?;
+        self.segments
+            .splice(start, end, insert.map(|insert| insert.segments));
+        self.len = len;
+        Ok(())
+    }
+
+    /// Whether both buffers have the same text written by the same sessions, however
+    /// it is split into segments
+    pub(crate) fn same_text(&self, other: &Self) -> bool {
+        fn runs<S: Storage>(buf: &SegmentBuffer<S>) -> impl Iterator<Item = Cow<'_, Segment>> {
+            buf.segments().filter(|s| !s.is_empty())
+        }
+        self.unit == other.unit && runs(self).eq(runs(other))
+    }
+
+    /// Segments with adjacent ones of the same user merged, which copies their text,
+    /// if storage splits them
+    pub(crate) fn segments(&self) -> impl Iterator<Item = Cow<'_, Segment>> + '_ {
+        self.segments.runs()
+    }
+
+    /// Segments as they are stored, without copying, see [`SegmentBuffer::segments`]
+    pub(crate) fn chunks(&self) -> impl Iterator<Item = &Segment> + '_ {
+        self.segments.chunks()
+    }
+
+    /// Runs of text written by the same session, with positions in units of buffer
+    pub fn authors(&self) -> impl Iterator<Item = (Range<TextPosition>, SessionId)> + '_ {
+        let mut segments = self.chunks().filter(|s| !s.is_empty()).peekable();
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let first = segments.next()?;
+            let mut len = self.unit.len(first);
+            while let Some(next) = segments.next_if(|s| s.user() == first.user()) {
+                len += self.unit.len(next);
+            }
+            pos += len;
+            Some((pos - len..pos, first.user()))
+        })
+    }
+    /// # Panics
+    ///
+    /// If range is out of buffer, see [`SegmentBuffer::try_authors_in`]
+    pub fn authors_in(
+        &self,
+        range: impl RangeBounds<TextPosition>,
+    ) -> Vec<(Range<TextPosition>, SessionId)> {
+        self.try_authors_in(range)
+            .unwrap_or_else(|e| panic!("authors_in: {}", e))
+    }
+    /// Runs of text in range, written by the same session, runs on the edges of range
+    /// are cut by it
+    pub fn try_authors_in(
+        &self,
+        range: impl RangeBounds<TextPosition>,
+    ) -> Result<Vec<(Range<TextPosition>, SessionId)>, OtError> {
+        let Range { start, end } = self.bounds(range);
+        let slice = self.try_slice(start..end)?;
+        let authors = slice.authors();
+        Ok(authors
+            .map(|(range, user)| (range.start + start..range.end + start, user))
+            .collect())
+    }
+    /// Length of text written by every session, in units of buffer
+    pub fn contribution_by_session(&self) -> BTreeMap<SessionId, TextSize> {
+        self.segments.contribution(self.unit)
+    }
+
+    pub fn len(&self) -> TextSize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+/// Reference implementation of [`SegmentBuffer`] as plain vector of authored bytes,
+/// used for differential testing
+#[cfg(any(test, feature = "testing"))]
+pub mod model {
+    use super::{Segment, SegmentBuffer, Storage};
+    use crate::{SessionId, TextPosition};
+
+    #[derive(Clone, Debug)]
+    pub enum Action {
+        /// Range may be out of buffer, in which case splice should fail
+        Splice {
+            start: TextPosition,
+            end: TextPosition,
+            insert: Vec<(SessionId, Vec<u8>)>,
+        },
+        Slice {
+            start: TextPosition,
+            end: TextPosition,
+        },
+        Compact,
+    }
+
+    fn buffer<S: Storage>(parts: &[(SessionId, Vec<u8>)]) -> SegmentBuffer<S> {
+        let mut buf = SegmentBuffer::default();
+        for (user, data) in parts.iter().filter(|(_, data)| !data.is_empty()) {
+            let len = buf.len();
+            let insert = SegmentBuffer::from_segments(Some(Segment::new(*user, data)));
+            buf.splice(len..len, Some(insert));
+        }
+        buf
+    }
+
+    fn authored(parts: &[(SessionId, Vec<u8>)]) -> Vec<(SessionId, u8)> {
+        parts
+            .iter()
+            .flat_map(|(user, data)| data.iter().map(move |b| (*user, *b)))
+            .collect()
+    }
+
+    fn check<S: Storage>(buf: &SegmentBuffer<S>, model: &[(SessionId, u8)]) -> Result<(), String> {
+        let actual = buf
+            .segments()
+            .flat_map(|s| s.iter().map(|b| (s.user(), *b)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if actual != model {
+            return Err(format!("contents {:?} != {:?}", actual, model));
+        }
+        if buf.len() != model.len() || buf.is_empty() != model.is_empty() {
+            return Err(format!("length {} != {}", buf.len(), model.len()));
+        }
+        if buf.segments().any(|s| s.is_empty()) {
+            return Err(format!("empty segment in {:?}", buf));
+        }
+        Ok(())
+    }
+
+    /// Applies actions to both buffer built from `initial` parts and the model,
+    /// fails on the first disagreement
+    pub fn run<S: Storage>(
+        initial: &[(SessionId, Vec<u8>)],
+        actions: &[Action],
+    ) -> Result<(), String> {
+        let mut buf = buffer::<S>(initial);
+        let mut model = authored(initial);
+        check(&buf, &model)?;
+        for action in actions {
+            match action {
+                Action::Splice { start, end, insert } => {
+                    let (start, end) = (*start, *end);
+                    let valid = start <= end && end <= model.len();
+                    let before = buf.clone();
+                    let result = buf.try_splice(start..end, Some(buffer(insert)));
+                    if !valid {
+                        if result.is_ok() || buf != before {
+                            return Err(format!("{:?} applied to {:?}", action, before));
+                        }
+                        continue;
+                    }
+                    result.map_err(|e| format!("{:?}: {}", action, e))?;
+                    model.splice(start..end, authored(insert));
+                    let segments = buf.segments().collect::<Vec<_>>();
+                    let adjacent = segments.windows(2);
+                    if let Some(pair) = adjacent.into_iter().find(|p| p[0].user() == p[1].user()) {
+                        return Err(format!("not compacted {:?}", pair));
+                    }
+                }
+                Action::Slice { start, end } => {
+                    let (start, end) = (*start, *end);
+                    match buf.try_slice(start..end) {
+                        Ok(slice) if start <= end && end <= model.len() => {
+                            check(&slice, &model[start..end])?
+                        }
+                        Err(_) if start > end || end > model.len() => {}
+                        result => return Err(format!("{:?} returned {:?}", action, result)),
+                    }
+                }
+                Action::Compact => buf.compact(),
+            }
+            check(&buf, &model).map_err(|e| format!("after {:?}: {}", action, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod compact {
+        use crate::segment::{Segment, SegmentBuffer};
+        use smallvec::smallvec;
+
+        #[test]
+        fn simple() {
+            let mut buf = SegmentBuffer::new(smallvec![
+                Segment(1, smallvec![1, 2]),
+                Segment(1, smallvec![3, 4]),
+            ]);
+            buf.compact();
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])])
+            );
+        }
+
+        #[test]
+        fn single() {
+            let mut buf = SegmentBuffer::new(smallvec![
+                Segment(1, smallvec![1, 2]),
+                Segment(1, smallvec![3, 4]),
+                Segment(2, smallvec![5]),
+            ]);
+            buf.compact();
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![
+                    Segment(1, smallvec![1, 2, 3, 4]),
+                    Segment(2, smallvec![5])
+                ])
+            );
+        }
+    }
+
+    mod slice {
+        use crate::segment::{Segment, SegmentBuffer};
+        use smallvec::smallvec;
+
+        #[test]
+        fn first() {
+            let input = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            assert_eq!(input.slice(0..=3), input);
+
+            let input = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            assert_eq!(input.slice(0..4), input);
+        }
+
+        #[test]
+        fn part() {
+            let input = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            assert_eq!(
+                input.slice(0..=2),
+                SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3])])
+            );
+
+            let input = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            assert_eq!(
+                input.slice(1..=3),
+                SegmentBuffer::new(smallvec![Segment(1, smallvec![2, 3, 4])])
+            );
+        }
+
+        #[test]
+        fn two() {
+            let input = SegmentBuffer::new(smallvec![
+                Segment(1, smallvec![1, 2, 3, 4]),
+                Segment(1, smallvec![5, 6, 7, 8])
+            ]);
+            assert_eq!(
+                input.slice(2..=5),
+                SegmentBuffer::new(smallvec![
+                    Segment(1, smallvec![3, 4]),
+                    Segment(1, smallvec![5, 6])
+                ])
+            );
+
+            let input = SegmentBuffer::new(smallvec![
+                Segment(1, smallvec![1, 2, 3, 4]),
+                Segment(1, smallvec![5, 6, 7, 8])
+            ]);
+            assert_eq!(input.slice(0..=7), input);
+        }
+    }
+
+    mod splice {
+        use crate::{
+            error::OtError,
+            segment::{Segment, SegmentBuffer},
+            unit::Unit,
+        };
+        use smallvec::smallvec;
+
+        #[test]
+        fn insert_start() {
+            let mut buf = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            buf.splice(
+                0..0,
+                Some(SegmentBuffer::new(smallvec![Segment(2, smallvec![5])])),
+            );
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![
+                    Segment(2, smallvec![5]),
+                    Segment(1, smallvec![1, 2, 3, 4])
+                ])
+            )
+        }
+
+        #[test]
+        fn insert_end() {
+            let mut buf = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            buf.splice(
+                buf.len..buf.len,
+                Some(SegmentBuffer::new(smallvec![Segment(2, smallvec![5])])),
+            );
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![
+                    Segment(1, smallvec![1, 2, 3, 4]),
+                    Segment(2, smallvec![5]),
+                ])
+            )
+        }
+
+        #[test]
+        fn insert_middle() {
+            let mut buf = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            buf.splice(
+                2..2,
+                Some(SegmentBuffer::new(smallvec![Segment(2, smallvec![5])])),
+            );
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![
+                    Segment(1, smallvec![1, 2]),
+                    Segment(2, smallvec![5]),
+                    Segment(1, smallvec![3, 4]),
+                ])
+            )
+        }
+
+        #[test]
+        fn replace_middle() {
+            let mut buf = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2, 3, 4])]);
+            buf.splice(
+                2..=2,
+                Some(SegmentBuffer::new(smallvec![Segment(2, smallvec![5])])),
+            );
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![
+                    Segment(1, smallvec![1, 2]),
+                    Segment(2, smallvec![5]),
+                    Segment(1, smallvec![4]),
+                ])
+            )
+        }
+
+        #[test]
+        fn replace_middle_overlap() {
+            let mut buf = SegmentBuffer::new(smallvec![
+                Segment(1, smallvec![1, 2]),
+                Segment(1, smallvec![3, 4])
+            ]);
+            buf.splice(
+                1..3,
+                Some(SegmentBuffer::new(smallvec![Segment(2, smallvec![5])])),
+            );
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![
+                    Segment(1, smallvec![1]),
+                    Segment(2, smallvec![5]),
+                    Segment(1, smallvec![4]),
+                ])
+            )
+        }
+
+        #[test]
+        fn unicode() {
+            let mut buf = SegmentBuffer::from_text(1, "a😀b", Unit::Utf16);
+            assert_eq!(buf.try_splice(2..2, None), Err(OtError::InsideCodePoint(2)));
+            buf.splice(1..3, Some(SegmentBuffer::from_text(2, "é", Unit::Chars)));
+            assert_eq!(buf.len(), 3);
+            assert_eq!(
+                buf.slice(1..2),
+                SegmentBuffer::from_text(2, "é", Unit::Utf16)
+            );
+            assert_eq!(buf.convert(2, Unit::Utf16, Unit::Bytes), Ok(3));
+            assert_eq!(
+                buf.convert(2, Unit::Bytes, Unit::Chars),
+                Err(OtError::InsideCodePoint(2))
+            );
+        }
+
+        #[test]
+        fn out_of_range() {
+            let mut buf = SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2])]);
+            assert_eq!(
+                buf.try_splice(1..3, None),
+                Err(OtError::OutOfRange { end: 3, len: 2 })
+            );
+            assert_eq!(
+                buf.try_splice(3..3, None),
+                Err(OtError::OutOfRange { end: 3, len: 2 })
+            );
+            assert_eq!(
+                buf,
+                SegmentBuffer::new(smallvec![Segment(1, smallvec![1, 2])])
+            );
+        }
+    }
+
+    mod authors {
+        use crate::{
+            segment::{tree::SegmentTree, Flat, Segment, SegmentBuffer, Storage},
+            unit::Unit,
+        };
+
+        fn buffer<S: Storage>() -> SegmentBuffer<S> {
+            SegmentBuffer::from_segments(vec![
+                Segment::new(1, "ab".as_bytes()),
+                Segment::new(1, "é".as_bytes()),
+                Segment::new(2, "😀c".as_bytes()),
+                Segment::new(1, "d".as_bytes()),
+            ])
+            .try_with_unit(Unit::Chars)
+            .unwrap()
+        }
+
+        fn check<S: Storage>() {
+            let buf = buffer::<S>();
+            assert_eq!(
+                buf.authors().collect::<Vec<_>>(),
+                vec![(0..3, 1), (3..5, 2), (5..6, 1)]
+            );
+            assert_eq!(buf.authors_in(2..4), vec![(2..3, 1), (3..4, 2)]);
+            assert_eq!(buf.authors_in(..), buf.authors().collect::<Vec<_>>());
+            assert!(buf.authors_in(4..4).is_empty());
+            assert!(buf.try_authors_in(4..7).is_err());
+            assert_eq!(
+                buf.contribution_by_session()
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+                vec![(1, 4), (2, 2)]
+            );
+            let buf = buf.try_with_unit(Unit::Utf16).unwrap();
+            assert_eq!(buf.contribution_by_session()[&2], 3);
+        }
+
+        #[test]
+        fn flat() {
+            check::<Flat>();
+        }
+
+        #[test]
+        fn tree() {
+            check::<SegmentTree>();
+        }
+    }
+
+    mod model {
+        use crate::segment::{
+            model::{run, Action},
+            tree::SegmentTree,
+            Flat,
+        };
+        use proptest::prelude::*;
+
+        fn parts() -> impl Strategy<Value = Vec<(u16, Vec<u8>)>> {
+            prop::collection::vec((0u16..3, prop::collection::vec(any::<u8>(), 1..4)), 0..4)
+        }
+
+        fn action() -> impl Strategy<Value = Action> {
+            prop_oneof![
+                4 => (0usize..24, 0usize..24, parts()).prop_map(|(a, b, insert)| {
+                    Action::Splice {
+                        start: a.min(b),
+                        end: a.max(b),
+                        insert,
+                    }
+                }),
+                2 => (0usize..24, 0usize..24).prop_map(|(a, b)| Action::Slice {
+                    start: a.min(b),
+                    end: a.max(b),
+                }),
+                1 => Just(Action::Compact),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn matches_byte_model(
+                initial in parts(),
+                actions in prop::collection::vec(action(), 0..32),
+            ) {
+                prop_assert_eq!(run::<Flat>(&initial, &actions), Ok(()));
+            }
+
+            #[test]
+            fn tree_matches_byte_model(
+                initial in parts(),
+                actions in prop::collection::vec(action(), 0..32),
+            ) {
+                prop_assert_eq!(run::<SegmentTree>(&initial, &actions), Ok(()));
+            }
+        }
+
+        #[test]
+        fn remove_first_segment() {
+            let actions = [Action::Splice {
+                start: 0,
+                end: 2,
+                insert: vec![],
+            }];
+            let initial = [(1, vec![1, 2]), (2, vec![3])];
+            assert_eq!(run::<Flat>(&initial, &actions), Ok(()));
+            assert_eq!(run::<SegmentTree>(&initial, &actions), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,771 @@
+//! Balanced tree storage for [`SegmentBuffer`](super::SegmentBuffer), for large documents
+//! with many authorship runs
+//!
+//! Runs are split into chunks of at most [`CHUNK`] bytes, which are kept in leaves of
+//! B-tree. Every node caches lengths of its text in all units, and bytes written by every
+//! session, so positions are found and edits are applied without walking all segments.
+
+use super::{Segment, Storage};
+use crate::{
+    unit::{is_boundary, Unit},
+    SessionId, TextPosition, TextSize,
+};
+use smallvec::SmallVec;
+use std::{borrow::Cow, collections::BTreeMap, fmt, iter::FromIterator, mem};
+
+// Tests use tiny nodes, so that small buffers already have several levels
+
+/// Maximum number of entries in node
+#[cfg(not(test))]
+const MAX: usize = 32;
+#[cfg(test)]
+const MAX: usize = 4;
+/// Nodes with less entries are merged with neighbours
+const MIN: usize = MAX / 2;
+/// Maximum length of chunk in bytes
+#[cfg(not(test))]
+pub const CHUNK: usize = 1024;
+#[cfg(test)]
+pub const CHUNK: usize = 4;
+
+#[derive(Clone, Copy, Default, Debug)]
+struct Lengths {
+    bytes: usize,
+    chars: TextSize,
+    utf16: TextSize,
+}
+impl Lengths {
+    fn of(data: &[u8]) -> Self {
+        Self {
+            bytes: data.len(),
+            chars: Unit::Chars.len(data),
+            utf16: Unit::Utf16.len(data),
+        }
+    }
+    fn get(&self, unit: Unit) -> TextSize {
+        match unit {
+            Unit::Bytes => self.bytes,
+            Unit::Chars => self.chars,
+            Unit::Utf16 => self.utf16,
+        }
+    }
+    fn add(&mut self, other: Lengths) {
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.utf16 += other.utf16;
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+struct Summary {
+    chunks: usize,
+    lengths: Lengths,
+    /// Text written by every session, ordered by session
+    authors: SmallVec<[(SessionId, Lengths); 2]>,
+}
+impl Summary {
+    fn add_author(&mut self, user: SessionId, lengths: Lengths) {
+        match self.authors.binary_search_by_key(&user, |(user, _)| *user) {
+            Ok(i) => self.authors[i].1.add(lengths),
+            Err(i) => self.authors.insert(i, (user, lengths)),
+        }
+    }
+    fn add(&mut self, other: &Summary) {
+        self.chunks += other.chunks;
+        self.lengths.add(other.lengths);
+        for (user, lengths) in other.authors.iter() {
+            self.add_author(*user, *lengths);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Chunk {
+    lengths: Lengths,
+    segment: Segment,
+}
+impl Chunk {
+    fn new(segment: Segment) -> Self {
+        Self {
+            lengths: Lengths::of(&segment),
+            segment,
+        }
+    }
+}
+
+/// Bytes of data which fit in chunk, without splitting code point of valid UTF-8
+fn fitting(data: &[u8], max: usize) -> usize {
+    if data.len() <= max {
+        return data.len();
+    }
+    (max.saturating_sub(3)..=max)
+        .rev()
+        .find(|at| is_boundary(data, *at))
+        .unwrap_or(max)
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Leaf(Summary, Vec<Chunk>),
+    Inner(Summary, Vec<Node>),
+}
+
+/// Child which contains index, and index inside of it
+fn child(children: &[Node], mut index: usize, inclusive: bool) -> (usize, usize) {
+    for (i, child) in children.iter().enumerate() {
+        let chunks = child.summary().chunks;
+        if index < chunks || inclusive && index == chunks {
+            return (i, index);
+        }
+        index -= chunks;
+    }
+    panic!("chunk index out of tree")
+}
+
+impl Node {
+    fn leaf(chunks: Vec<Chunk>) -> Self {
+        let mut node = Node::Leaf(Summary::default(), chunks);
+        node.update();
+        node
+    }
+    fn inner(children: Vec<Node>) -> Self {
+        let mut node = Node::Inner(Summary::default(), children);
+        node.update();
+        node
+    }
+    fn summary(&self) -> &Summary {
+        match self {
+            Node::Leaf(summary, _) | Node::Inner(summary, _) => summary,
+        }
+    }
+    fn entries(&self) -> usize {
+        match self {
+            Node::Leaf(_, chunks) => chunks.len(),
+            Node::Inner(_, children) => children.len(),
+        }
+    }
+    fn update(&mut self) {
+        let mut out = Summary::default();
+        match self {
+            Node::Leaf(summary, chunks) => {
+                for chunk in chunks.iter() {
+                    out.chunks += 1;
+                    out.lengths.add(chunk.lengths);
+                    out.add_author(chunk.segment.user(), chunk.lengths);
+                }
+                *summary = out;
+            }
+            Node::Inner(summary, children) => {
+                for child in children.iter() {
+                    out.add(child.summary());
+                }
+                *summary = out;
+            }
+        }
+    }
+    /// Moves second half of entries to the new node
+    fn split_half(&mut self) -> Node {
+        let right = match self {
+            Node::Leaf(_, chunks) => Node::leaf(chunks.split_off(chunks.len() / 2)),
+            Node::Inner(_, children) => Node::inner(children.split_off(children.len() / 2)),
+        };
+        self.update();
+        right
+    }
+    fn append(&mut self, other: Node) {
+        match (&mut *self, other) {
+            (Node::Leaf(_, chunks), Node::Leaf(_, other)) => chunks.extend(other),
+            (Node::Inner(_, children), Node::Inner(_, other)) => children.extend(other),
+            _ => unreachable!("leaves are at the same depth"),
+        }
+        self.update();
+    }
+
+    fn get(&self, index: usize) -> &Chunk {
+        match self {
+            Node::Leaf(_, chunks) => &chunks[index],
+            Node::Inner(_, children) => {
+                let (i, index) = child(children, index, false);
+                children[i].get(index)
+            }
+        }
+    }
+    /// Returns new right sibling, if node was overflown
+    fn insert(&mut self, index: usize, chunk: Chunk) -> Option<Node> {
+        match self {
+            Node::Leaf(_, chunks) => chunks.insert(index, chunk),
+            Node::Inner(_, children) => {
+                let (i, index) = child(children, index, true);
+                if let Some(right) = children[i].insert(index, chunk) {
+                    children.insert(i + 1, right);
+                }
+            }
+        }
+        if self.entries() > MAX {
+            return Some(self.split_half());
+        }
+        self.update();
+        None
+    }
+    fn remove(&mut self, index: usize) -> Chunk {
+        let chunk = match self {
+            Node::Leaf(_, chunks) => chunks.remove(index),
+            Node::Inner(_, children) => {
+                let (i, index) = child(children, index, false);
+                let chunk = children[i].remove(index);
+                if children[i].entries() < MIN && children.len() > 1 {
+                    // Merge with neighbour, and split again if it was big enough
+                    let i = i.min(children.len() - 2);
+                    let right = children.remove(i + 1);
+                    children[i].append(right);
+                    if children[i].entries() > MAX {
+                        let right = children[i].split_half();
+                        children.insert(i + 1, right);
+                    }
+                }
+                chunk
+            }
+        };
+        self.update();
+        chunk
+    }
+    fn replace(&mut self, index: usize, chunk: Chunk) -> Chunk {
+        let old = match self {
+            Node::Leaf(_, chunks) => mem::replace(&mut chunks[index], chunk),
+            Node::Inner(_, children) => {
+                let (i, index) = child(children, index, false);
+                children[i].replace(index, chunk)
+            }
+        };
+        self.update();
+        old
+    }
+
+    fn find(
+        &self,
+        unit: Unit,
+        mut rest: TextPosition,
+        mut offset: usize,
+    ) -> (Option<&Segment>, TextPosition, usize) {
+        match self {
+            Node::Leaf(_, chunks) => {
+                for chunk in chunks.iter() {
+                    let len = chunk.lengths.get(unit);
+                    if rest < len {
+                        return (Some(&chunk.segment), rest, offset);
+                    }
+                    rest -= len;
+                    offset += chunk.lengths.bytes;
+                }
+            }
+            Node::Inner(_, children) => {
+                for child in children.iter() {
+                    let lengths = child.summary().lengths;
+                    if rest < lengths.get(unit) {
+                        return child.find(unit, rest, offset);
+                    }
+                    rest -= lengths.get(unit);
+                    offset += lengths.bytes;
+                }
+            }
+        }
+        (None, rest, offset)
+    }
+    /// Index of chunk which contains byte, and offset in it
+    fn locate(&self, mut bytes: usize) -> (usize, usize) {
+        let mut index = 0;
+        match self {
+            Node::Leaf(_, chunks) => {
+                for chunk in chunks.iter() {
+                    if bytes < chunk.lengths.bytes {
+                        return (index, bytes);
+                    }
+                    bytes -= chunk.lengths.bytes;
+                    index += 1;
+                }
+            }
+            Node::Inner(_, children) => {
+                for child in children.iter() {
+                    let summary = child.summary();
+                    if bytes < summary.lengths.bytes {
+                        let (inner, bytes) = child.locate(bytes);
+                        return (index + inner, bytes);
+                    }
+                    bytes -= summary.lengths.bytes;
+                    index += summary.chunks;
+                }
+            }
+        }
+        (index, bytes)
+    }
+    fn measure(&self, unit: Unit, mut bytes: usize) -> TextSize {
+        let mut out = 0;
+        match self {
+            Node::Leaf(_, chunks) => {
+                for chunk in chunks.iter() {
+                    if bytes < chunk.lengths.bytes {
+                        return out + unit.len(&chunk.segment[..bytes]);
+                    }
+                    bytes -= chunk.lengths.bytes;
+                    out += chunk.lengths.get(unit);
+                }
+            }
+            Node::Inner(_, children) => {
+                for child in children.iter() {
+                    let lengths = child.summary().lengths;
+                    if bytes < lengths.bytes {
+                        return out + child.measure(unit, bytes);
+                    }
+                    bytes -= lengths.bytes;
+                    out += lengths.get(unit);
+                }
+            }
+        }
+        out
+    }
+    /// Pushes parts of chunks in byte range
+    fn collect(&self, start: usize, end: usize, out: &mut Vec<Segment>) {
+        let mut offset = 0;
+        match self {
+            Node::Leaf(_, chunks) => {
+                for chunk in chunks.iter() {
+                    let len = chunk.lengths.bytes;
+                    if offset + len > start {
+                        let range = start.saturating_sub(offset)..(end - offset).min(len);
+                        out.push(Segment::new(chunk.segment.user(), &chunk.segment[range]));
+                    }
+                    offset += len;
+                    if offset >= end {
+                        break;
+                    }
+                }
+            }
+            Node::Inner(_, children) => {
+                for child in children.iter() {
+                    let len = child.summary().lengths.bytes;
+                    if offset + len > start {
+                        child.collect(start.saturating_sub(offset), end - offset, out);
+                    }
+                    offset += len;
+                    if offset >= end {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    fn into_chunks(self, out: &mut Vec<Chunk>) {
+        match self {
+            Node::Leaf(_, chunks) => out.extend(chunks),
+            Node::Inner(_, children) => {
+                for child in children {
+                    child.into_chunks(out);
+                }
+            }
+        }
+    }
+}
+
+/// Splits entries to groups of at most [`MAX`], and at least [`MIN`] if there is more than one
+fn group<T>(mut entries: Vec<T>) -> Vec<Vec<T>> {
+    let groups = entries.len().div_ceil(MAX);
+    if groups <= 1 {
+        return vec![entries];
+    }
+    let mut out = Vec::with_capacity(groups);
+    for i in (0..groups).rev() {
+        let size = entries.len() / (i + 1);
+        out.push(entries.split_off(entries.len() - size));
+    }
+    out.reverse();
+    out
+}
+
+/// B-tree of chunks, adjacent chunks of the same session are always reported as single
+/// segment, so there is nothing to compact
+#[derive(Clone)]
+pub struct SegmentTree {
+    root: Node,
+}
+impl Default for SegmentTree {
+    fn default() -> Self {
+        Self {
+            root: Node::leaf(Vec::new()),
+        }
+    }
+}
+impl SegmentTree {
+    fn build(chunks: Vec<Chunk>) -> Self {
+        let mut nodes = group(chunks)
+            .into_iter()
+            .map(Node::leaf)
+            .collect::<Vec<_>>();
+        while nodes.len() > 1 {
+            nodes = group(nodes).into_iter().map(Node::inner).collect();
+        }
+        Self {
+            root: nodes.pop().expect("at least one leaf"),
+        }
+    }
+    fn iter_chunks(&self) -> Chunks<'_> {
+        match &self.root {
+            Node::Leaf(_, chunks) => Chunks {
+                stack: Vec::new(),
+                leaf: chunks.iter(),
+            },
+            Node::Inner(_, children) => Chunks {
+                stack: vec![children.iter()],
+                leaf: [].iter(),
+            },
+        }
+    }
+    fn count(&self) -> usize {
+        self.root.summary().chunks
+    }
+    fn insert(&mut self, index: usize, chunk: Chunk) {
+        if let Some(right) = self.root.insert(index, chunk) {
+            let left = mem::replace(&mut self.root, Node::leaf(Vec::new()));
+            self.root = Node::inner(vec![left, right]);
+        }
+    }
+    fn remove(&mut self, index: usize) -> Chunk {
+        let chunk = self.root.remove(index);
+        if let Node::Inner(_, children) = &mut self.root {
+            if children.len() == 1 {
+                self.root = children.pop().expect("single child");
+            }
+        }
+        chunk
+    }
+    /// Splits chunk at byte offset, returns index of chunk which starts at it
+    fn split(&mut self, bytes: usize) -> usize {
+        let (index, rest) = self.root.locate(bytes);
+        if rest == 0 {
+            return index;
+        }
+        let segment = &self.root.get(index).segment;
+        let (user, right) = (
+            segment.user(),
+            Segment::new(segment.user(), &segment[rest..]),
+        );
+        let left = Segment::new(user, &segment[..rest]);
+        self.root.replace(index, Chunk::new(left));
+        self.insert(index + 1, Chunk::new(right));
+        index + 1
+    }
+    /// Merges chunk with the next one, if they have the same author, and fit in one chunk
+    fn try_merge(&mut self, index: usize) -> bool {
+        let (left, right) = (self.root.get(index), self.root.get(index + 1));
+        if left.segment.user() != right.segment.user()
+            || left.lengths.bytes + right.lengths.bytes > CHUNK
+        {
+            return false;
+        }
+        let mut merged = left.segment.clone();
+        merged.extend_from_slice(&right.segment);
+        self.remove(index + 1);
+        self.root.replace(index, Chunk::new(merged));
+        true
+    }
+
+    /// Depth of the tree, leaf-only tree has depth of 1
+    pub fn depth(&self) -> usize {
+        let mut node = &self.root;
+        let mut depth = 1;
+        while let Node::Inner(_, children) = node {
+            node = &children[0];
+            depth += 1;
+        }
+        depth
+    }
+}
+
+impl FromIterator<Segment> for SegmentTree {
+    fn from_iter<T: IntoIterator<Item = Segment>>(iter: T) -> Self {
+        let mut chunks: Vec<Chunk> = Vec::new();
+        for segment in iter {
+            let mut data = &segment[..];
+            if let Some(last) = chunks
+                .last_mut()
+                .filter(|last| last.segment.user() == segment.user())
+            {
+                let len = fitting(data, CHUNK - last.lengths.bytes.min(CHUNK));
+                last.segment.extend_from_slice(&data[..len]);
+                last.lengths = Lengths::of(&last.segment);
+                data = &data[len..];
+            }
+            while !data.is_empty() {
+                let len = fitting(data, CHUNK);
+                chunks.push(Chunk::new(Segment::new(segment.user(), &data[..len])));
+                data = &data[len..];
+            }
+        }
+        Self::build(chunks)
+    }
+}
+
+struct Chunks<'a> {
+    stack: Vec<std::slice::Iter<'a, Node>>,
+    leaf: std::slice::Iter<'a, Chunk>,
+}
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(chunk) = self.leaf.next() {
+                return Some(chunk);
+            }
+            match self.stack.last_mut()?.next() {
+                Some(Node::Leaf(_, chunks)) => self.leaf = chunks.iter(),
+                Some(Node::Inner(_, children)) => self.stack.push(children.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl Storage for SegmentTree {
+    fn runs(&self) -> impl Iterator<Item = Cow<'_, Segment>> {
+        let mut chunks = self.iter_chunks().peekable();
+        std::iter::from_fn(move || {
+            let first = chunks.next()?;
+            let mut run = Cow::Borrowed(&first.segment);
+            while let Some(next) = chunks.next_if(|c| c.segment.user() == first.segment.user()) {
+                run.to_mut().extend_from_slice(&next.segment);
+            }
+            Some(run)
+        })
+    }
+    fn chunks(&self) -> impl Iterator<Item = &Segment> {
+        self.iter_chunks().map(|chunk| &chunk.segment)
+    }
+    fn len_in(&self, unit: Unit) -> TextSize {
+        self.root.summary().lengths.get(unit)
+    }
+    fn find(&self, unit: Unit, pos: TextPosition) -> (Option<&Segment>, TextPosition, usize) {
+        self.root.find(unit, pos, 0)
+    }
+    fn measure(&self, unit: Unit, bytes: usize) -> TextSize {
+        self.root.measure(unit, bytes)
+    }
+    fn slice(&self, start: usize, end: usize) -> Self {
+        let mut segments = Vec::new();
+        if start < end {
+            self.root.collect(start, end, &mut segments);
+        }
+        segments.into_iter().collect()
+    }
+    fn splice(&mut self, start: usize, end: usize, insert: Option<Self>) {
+        let start = self.split(start);
+        let end = self.split(end);
+        for _ in start..end {
+            self.remove(start);
+        }
+        let mut inserted = Vec::new();
+        if let Some(insert) = insert {
+            insert.root.into_chunks(&mut inserted);
+        }
+        let mut end = start + inserted.len();
+        for (i, chunk) in inserted.into_iter().enumerate() {
+            self.insert(start + i, chunk);
+        }
+        // Only chunks around the edit may be merged
+        let mut index = start.saturating_sub(1);
+        while index < end && index + 1 < self.count() {
+            if self.try_merge(index) {
+                end -= 1;
+            } else {
+                index += 1;
+            }
+        }
+    }
+    fn contribution(&self, unit: Unit) -> BTreeMap<SessionId, TextSize> {
+        let authors = self.root.summary().authors.iter();
+        authors
+            .map(|(user, lengths)| (*user, lengths.get(unit)))
+            .collect()
+    }
+    fn compact(&mut self) {}
+    fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+}
+
+impl PartialEq for SegmentTree {
+    /// Trees may split the same text into chunks differently, so overlapping parts of
+    /// chunks are compared
+    fn eq(&self, other: &Self) -> bool {
+        let mut chunks = (Storage::chunks(self), Storage::chunks(other));
+        /// Not yet compared rest of chunk
+        type Rest<'a> = Option<(SessionId, &'a [u8])>;
+        let (mut a, mut b): (Rest, Rest) = (None, None);
+        loop {
+            a = a
+                .filter(|(_, data)| !data.is_empty())
+                .or_else(|| chunks.0.next().map(|s| (s.user(), &s[..])));
+            b = b
+                .filter(|(_, data)| !data.is_empty())
+                .or_else(|| chunks.1.next().map(|s| (s.user(), &s[..])));
+            match (a, b) {
+                (None, None) => return true,
+                (Some((user_a, data_a)), Some((user_b, data_b))) => {
+                    let len = data_a.len().min(data_b.len());
+                    if user_a != user_b || data_a[..len] != data_b[..len] {
+                        return false;
+                    }
+                    a = Some((user_a, &data_a[len..]));
+                    b = Some((user_b, &data_b[len..]));
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+impl Eq for SegmentTree {}
+
+impl fmt::Debug for SegmentTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.runs()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Node, SegmentTree, CHUNK, MAX, MIN};
+    use crate::{
+        segment::{Flat, Segment, SegmentBuffer},
+        unit::Unit,
+    };
+    use proptest::prelude::*;
+    use std::borrow::Cow;
+
+    fn buffer(user: u16, data: &[u8]) -> SegmentBuffer<SegmentTree> {
+        SegmentBuffer::from_segments(Some(Segment::new(user, data)))
+    }
+
+    /// Fails if node sizes, depths or summaries are broken, returns depth
+    fn check(node: &Node, root: bool) -> usize {
+        let mut expected = node.clone();
+        expected.update();
+        assert_eq!(
+            format!("{:?}", node.summary()),
+            format!("{:?}", expected.summary())
+        );
+        assert!(node.entries() <= MAX);
+        assert!(root || node.entries() >= MIN, "underflown {:?}", node);
+        match node {
+            Node::Leaf(_, chunks) => {
+                assert!(chunks
+                    .iter()
+                    .all(|c| !c.segment.is_empty() && c.segment.len() <= CHUNK));
+                1
+            }
+            Node::Inner(_, children) => {
+                assert!(!root || children.len() > 1);
+                let depths = children.iter().map(|c| check(c, false)).collect::<Vec<_>>();
+                assert!(depths.iter().all(|d| *d == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn stays_balanced(
+            initial in prop::collection::vec(any::<u8>(), 0..64),
+            edits in prop::collection::vec(
+                (any::<prop::sample::Index>(), 0usize..32, 0u16..3, 0usize..16),
+                0..32,
+            ),
+        ) {
+            let mut buf = buffer(0, &initial);
+            let mut expected = initial;
+            check(&buf.segments.root, true);
+            for (start, removed, user, inserted) in edits {
+                let start = start.index(expected.len() + 1);
+                let end = (start + removed).min(expected.len());
+                let data = vec![user as u8; inserted];
+                buf.splice(start..end, Some(buffer(user, &data)));
+                expected.splice(start..end, data);
+                check(&buf.segments.root, true);
+                prop_assert_eq!(buf.segments.root.summary().lengths.bytes, expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn large() {
+        let data = vec![b'a'; CHUNK * MAX * MAX * 2];
+        let mut buf = buffer(1, &data);
+        assert_eq!(buf.segments.depth(), 3);
+        assert_eq!(check(&buf.segments.root, true), 3);
+        assert_eq!(buf.segments().count(), 1);
+
+        let mut expected = data.clone();
+        for i in 0..200 {
+            let pos = i * 7919 % buf.len();
+            buf.splice(pos..pos, Some(buffer(2 + i as u16 % 2, b"bc")));
+            expected.splice(pos..pos, b"bc".iter().copied());
+        }
+        buf.splice(100..data.len(), None);
+        expected.splice(100..data.len(), None);
+
+        let actual = buf
+            .segments()
+            .flat_map(|s| s.iter().copied().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+        assert_eq!(buf.len(), expected.len());
+        check(&buf.segments.root, true);
+        let flat = SegmentBuffer::<Flat>::from_segments(buf.segments().map(Cow::into_owned));
+        assert_eq!(
+            buf.contribution_by_session(),
+            flat.contribution_by_session()
+        );
+        assert_eq!(
+            buf.contribution_by_session().values().sum::<usize>(),
+            expected.len()
+        );
+    }
+
+    #[test]
+    fn equal_text_split_differently() {
+        let tree = |segments: &[(u16, &[u8])]| {
+            segments
+                .iter()
+                .map(|(user, data)| Segment::new(*user, data))
+                .collect::<SegmentTree>()
+        };
+        let whole = tree(&[(1, b"abcd"), (2, b"e")]);
+        let mut split = whole.clone();
+        split.split(1);
+        split.split(3);
+        assert_eq!(split.count(), 4);
+        assert_eq!(whole, split);
+        assert_ne!(whole, tree(&[(1, b"abc"), (2, b"de")]));
+        assert_ne!(whole, tree(&[(1, b"abcd")]));
+        assert_ne!(whole, tree(&[(1, b"abcd"), (2, b"e"), (3, b"f")]));
+    }
+
+    #[test]
+    fn chunks_keep_code_points() {
+        let text = format!("a{}", "é".repeat(CHUNK));
+        let buf = buffer(1, text.as_bytes())
+            .try_with_unit(Unit::Chars)
+            .unwrap();
+        assert_eq!(buf.len(), CHUNK + 1);
+        assert_eq!(
+            buf.convert(CHUNK / 2 + 1, Unit::Chars, Unit::Bytes),
+            Ok(CHUNK + 1)
+        );
+        assert_eq!(
+            buf.slice(CHUNK / 2..CHUNK / 2 + 2),
+            SegmentBuffer::from_segments(Some(Segment::new(1, "éé".as_bytes())))
+                .try_with_unit(Unit::Chars)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,713 @@
+//! Serde support, JSON representation mirrors infinote requests:
+//!
+//! ```json
+//! {"type":"do","version":1,"user":1,"time":"1:2;3:1","operation":{"type":"insert","pos":0,"text":[{"author":1,"text":"hi"}]}}
+//! {"type":"undo","version":1,"user":1,"time":"1:3;3:1"}
+//! ```
+//!
+//! Positions and lengths are counted in unit of the sending state, see [`crate::unit`].
+//! Text, which is not valid utf-8, is only possible in buffers measured in bytes, such
+//! segments are written as `{"author":1,"bytes":[255,0]}`.
+//! Snapshot contains buffer, its unit (omitted for bytes), state, log and registered
+//! sessions:
+//!
+//! ```json
+//! {"version":1,"unit":"utf16","buffer":[{"author":1,"text":"hi"}],"time":"1:2","log":[...],
+//!  "sessions":{"3":{"name":"bob","colour":255,"present":true,"time":"1:1;3:1"}}}
+//! ```
+use std::collections::BTreeMap;
+
+use crate::{
+    op::{Delete, Insert, Operation, Split},
+    recon::Recon,
+    request::{dor::DoRequest, redo::RedoRequest, undo::UndoRequest, Request},
+    segment::{Segment, SegmentBuffer},
+    session::{Session, SessionInfo, SessionRegistry},
+    snapshot::Snapshot,
+    unit::Unit,
+    vector::StateVector,
+    SessionId, TextPosition, TextSize,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of request representation, bumped on every incompatible change
+pub const VERSION: u32 = 1;
+
+/// Segment has either `text`, or `bytes`, if its text is not valid utf-8
+#[derive(Serialize)]
+struct SegmentSer<'a> {
+    author: SessionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<&'a [u8]>,
+}
+
+#[derive(Deserialize)]
+struct SegmentDe {
+    author: SessionId,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    bytes: Option<Vec<u8>>,
+}
+
+impl Serialize for SegmentBuffer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let segments = self.segments().collect::<Vec<_>>();
+        serializer.collect_seq(segments.iter().map(|segment| {
+            let text = std::str::from_utf8(segment).ok();
+            SegmentSer {
+                author: segment.user(),
+                text,
+                bytes: Some(&segment[..]).filter(|_| text.is_none()),
+            }
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for SegmentBuffer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let segments = Vec::<SegmentDe>::deserialize(deserializer)?;
+        let segments = segments
+            .into_iter()
+            .map(|s| match (s.text, s.bytes) {
+                (Some(text), None) => Ok(Segment::new(s.author, text.as_bytes())),
+                (None, Some(bytes)) => Ok(Segment::new(s.author, &bytes)),
+                _ => Err(de::Error::custom(
+                    "segment should have either text or bytes",
+                )),
+            })
+            .filter(|s| s.as_ref().map_or(true, |s| !s.is_empty()))
+            .collect::<Result<_, _>>()?;
+        Ok(SegmentBuffer::new(segments))
+    }
+}
+
+impl Serialize for Unit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Unit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let unit = String::deserialize(deserializer)?;
+        unit.parse().map_err(de::Error::custom)
+    }
+}
+
+fn is_bytes(unit: &Unit) -> bool {
+    *unit == Unit::Bytes
+}
+
+impl Serialize for StateVector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StateVector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        time.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize)]
+struct ReconSegmentSer<'a> {
+    offset: usize,
+    text: &'a SegmentBuffer,
+}
+
+#[derive(Deserialize)]
+struct ReconSegmentDe {
+    offset: usize,
+    text: SegmentBuffer,
+}
+
+impl Serialize for Recon {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(|segment| ReconSegmentSer {
+            offset: segment.offset,
+            text: &segment.buffer,
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for Recon {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let segments = Vec::<ReconSegmentDe>::deserialize(deserializer)?;
+        let mut recon = Recon::new();
+        for segment in segments {
+            recon.add(segment.offset, segment.text);
+        }
+        Ok(recon)
+    }
+}
+
+fn recon_is_empty(recon: &&Recon) -> bool {
+    recon.is_empty()
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum OperationSer<'a> {
+    NoOp,
+    Insert {
+        pos: TextPosition,
+        text: &'a SegmentBuffer,
+    },
+    Delete {
+        pos: TextPosition,
+        len: TextSize,
+        /// Only present for reversible deletes
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<&'a SegmentBuffer>,
+        #[serde(skip_serializing_if = "recon_is_empty")]
+        recon: &'a Recon,
+    },
+    Split {
+        first: &'a Operation,
+        second: &'a Operation,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum OperationDe {
+    NoOp,
+    Insert {
+        pos: TextPosition,
+        text: SegmentBuffer,
+    },
+    Delete {
+        pos: TextPosition,
+        len: TextSize,
+        #[serde(default)]
+        text: Option<SegmentBuffer>,
+        #[serde(default)]
+        recon: Recon,
+    },
+    Split {
+        first: Operation,
+        second: Operation,
+    },
+}
+
+impl Serialize for Operation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Operation::NoOp => OperationSer::NoOp,
+            Operation::Insert(insert) => OperationSer::Insert {
+                pos: insert.position,
+                text: insert.buffer(),
+            },
+            Operation::Delete(delete) => OperationSer::Delete {
+                pos: delete.position,
+                len: delete.len(),
+                text: delete.buf(),
+                recon: &delete.recon,
+            },
+            Operation::Split(split) => OperationSer::Split {
+                first: &split.0,
+                second: &split.1,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Operation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match OperationDe::deserialize(deserializer)? {
+            OperationDe::NoOp => Operation::NoOp,
+            OperationDe::Insert { pos, text } => Insert::new(pos, text).into(),
+            OperationDe::Delete {
+                pos,
+                len,
+                text,
+                recon,
+            } => match text {
+                // Length is counted in unit of the sending state, which is only known to
+                // the receiving state
+                Some(text) if Unit::ALL.iter().all(|unit| text.len_in(*unit) != len) => {
+                    return Err(de::Error::custom("delete text doesn't match its length"))
+                }
+                Some(text) => Delete::new(pos, Ok(text), recon).into(),
+                None => Delete::new(pos, Err(len), recon).into(),
+            },
+            OperationDe::Split { first, second } => Split::new(first, second).into(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DoRequestRepr<O> {
+    user: SessionId,
+    time: StateVector,
+    operation: O,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UndoRedoRepr {
+    user: SessionId,
+    time: StateVector,
+}
+
+impl Serialize for DoRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DoRequestRepr {
+            user: self.user,
+            time: self.vector.clone(),
+            operation: self.operation(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DoRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = DoRequestRepr::<Operation>::deserialize(deserializer)?;
+        Ok(DoRequest::new(repr.user, repr.time, repr.operation))
+    }
+}
+
+impl Serialize for UndoRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UndoRedoRepr {
+            user: self.user,
+            time: self.vector.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for UndoRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = UndoRedoRepr::deserialize(deserializer)?;
+        Ok(UndoRequest {
+            user: repr.user,
+            vector: repr.time,
+        })
+    }
+}
+
+impl Serialize for RedoRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UndoRedoRepr {
+            user: self.user,
+            time: self.vector.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RedoRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = UndoRedoRepr::deserialize(deserializer)?;
+        Ok(RedoRequest {
+            user: repr.user,
+            vector: repr.time,
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum RequestSer<'a> {
+    Do {
+        version: u32,
+        user: SessionId,
+        time: &'a StateVector,
+        operation: &'a Operation,
+    },
+    Undo {
+        version: u32,
+        user: SessionId,
+        time: &'a StateVector,
+    },
+    Redo {
+        version: u32,
+        user: SessionId,
+        time: &'a StateVector,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum RequestDe {
+    Do {
+        version: u32,
+        user: SessionId,
+        time: StateVector,
+        operation: Operation,
+    },
+    Undo {
+        version: u32,
+        user: SessionId,
+        time: StateVector,
+    },
+    Redo {
+        version: u32,
+        user: SessionId,
+        time: StateVector,
+    },
+}
+
+impl Serialize for Request {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Request::Do(dor) => RequestSer::Do {
+                version: VERSION,
+                user: dor.user,
+                time: &dor.vector,
+                operation: dor.operation(),
+            },
+            Request::Undo(undo) => RequestSer::Undo {
+                version: VERSION,
+                user: undo.user,
+                time: &undo.vector,
+            },
+            Request::Redo(redo) => RequestSer::Redo {
+                version: VERSION,
+                user: redo.user,
+                time: &redo.vector,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Request {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (version, request) = match RequestDe::deserialize(deserializer)? {
+            RequestDe::Do {
+                version,
+                user,
+                time,
+                operation,
+            } => (version, Request::Do(DoRequest::new(user, time, operation))),
+            RequestDe::Undo {
+                version,
+                user,
+                time,
+            } => (version, Request::Undo(UndoRequest { user, vector: time })),
+            RequestDe::Redo {
+                version,
+                user,
+                time,
+            } => (version, Request::Redo(RedoRequest { user, vector: time })),
+        };
+        if version != VERSION {
+            return Err(de::Error::custom(format!(
+                "unsupported request version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Serialize)]
+struct SessionSer<'a> {
+    name: &'a str,
+    colour: u32,
+    present: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<&'a StateVector>,
+}
+
+#[derive(Deserialize)]
+struct SessionDe {
+    name: String,
+    colour: u32,
+    present: bool,
+    #[serde(default)]
+    time: Option<StateVector>,
+}
+
+impl Serialize for SessionRegistry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter().map(|(id, session)| {
+            (
+                id,
+                SessionSer {
+                    name: &session.info.name,
+                    colour: session.info.colour,
+                    present: session.is_present(),
+                    time: session.vector(),
+                },
+            )
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for SessionRegistry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let sessions = BTreeMap::<SessionId, SessionDe>::deserialize(deserializer)?;
+        let mut registry = SessionRegistry::default();
+        for (id, session) in sessions {
+            registry.insert(
+                id,
+                Session {
+                    info: SessionInfo::new(session.name, session.colour),
+                    // Without known state, session may need any request
+                    vector: session.time.unwrap_or_default(),
+                    present: session.present,
+                    local: false,
+                },
+            );
+        }
+        Ok(registry)
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotSer<'a> {
+    version: u32,
+    #[serde(skip_serializing_if = "is_bytes")]
+    unit: Unit,
+    buffer: &'a SegmentBuffer,
+    time: &'a StateVector,
+    log: &'a [Request],
+    sessions: &'a SessionRegistry,
+}
+
+#[derive(Deserialize)]
+struct SnapshotDe {
+    version: u32,
+    #[serde(default)]
+    unit: Unit,
+    buffer: SegmentBuffer,
+    time: StateVector,
+    log: Vec<Request>,
+    sessions: SessionRegistry,
+}
+
+impl Serialize for Snapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SnapshotSer {
+            version: VERSION,
+            unit: self.buffer.unit(),
+            buffer: &self.buffer,
+            time: &self.vector,
+            log: &self.log,
+            sessions: &self.sessions,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Snapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = SnapshotDe::deserialize(deserializer)?;
+        if snapshot.version != VERSION {
+            return Err(de::Error::custom(format!(
+                "unsupported snapshot version {}, expected {}",
+                snapshot.version, VERSION
+            )));
+        }
+        Ok(Snapshot {
+            buffer: snapshot
+                .buffer
+                .try_with_unit(snapshot.unit)
+                .map_err(de::Error::custom)?,
+            vector: snapshot.time,
+            log: snapshot.log,
+            sessions: snapshot.sessions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        op::{Delete, Insert, Operation, Split},
+        recon::Recon,
+        request::{dor::DoRequest, undo::UndoRequest, Request},
+        segment::{Segment, SegmentBuffer},
+        session::SessionInfo,
+        snapshot::Snapshot,
+        unit::Unit,
+        vector::StateVector,
+        State, NO_OWNER,
+    };
+    use serde_json::json;
+    use smallvec::smallvec;
+
+    fn vector() -> StateVector {
+        let mut vector = StateVector::new();
+        vector.set(1, 2);
+        vector.set(3, 1);
+        vector
+    }
+
+    #[test]
+    fn insert() {
+        let request = Request::Do(DoRequest::new(
+            1,
+            vector(),
+            Insert::new(0, SegmentBuffer::from_bytes(1, b"hi")).into(),
+        ));
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "type": "do",
+                "version": 1,
+                "user": 1,
+                "time": "1:2;3:1",
+                "operation": {
+                    "type": "insert",
+                    "pos": 0,
+                    "text": [{"author": 1, "text": "hi"}],
+                },
+            })
+        );
+        let parsed: Request = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), value);
+    }
+
+    #[test]
+    fn delete() {
+        let mut recon = Recon::new();
+        recon.add(1, SegmentBuffer::from_bytes(2, b"x"));
+        let operation: Operation = Split::new(
+            Delete::new(
+                1,
+                Ok(SegmentBuffer::new(smallvec![
+                    Segment::new(1, b"a"),
+                    Segment::new(2, b"b"),
+                ])),
+                Recon::new(),
+            ),
+            Delete::new(5, Err(3), recon),
+        )
+        .into();
+        let value = serde_json::to_value(&operation).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "type": "split",
+                "first": {
+                    "type": "delete",
+                    "pos": 1,
+                    "len": 2,
+                    "text": [{"author": 1, "text": "a"}, {"author": 2, "text": "b"}],
+                },
+                "second": {
+                    "type": "delete",
+                    "pos": 5,
+                    "len": 3,
+                    "recon": [{"offset": 1, "text": [{"author": 2, "text": "x"}]}],
+                },
+            })
+        );
+        let parsed: Operation = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), value);
+    }
+
+    #[test]
+    fn non_utf8_text() {
+        let buffer =
+            SegmentBuffer::new(smallvec![Segment::new(1, b"a\xff"), Segment::new(2, b"b")]);
+        let value = serde_json::to_value(&buffer).unwrap();
+        assert_eq!(
+            value,
+            json!([{"author": 1, "bytes": [97, 255]}, {"author": 2, "text": "b"}])
+        );
+        assert_eq!(
+            serde_json::from_value::<SegmentBuffer>(value).unwrap(),
+            buffer
+        );
+        assert!(serde_json::from_value::<SegmentBuffer>(
+            json!([{"author": 1, "text": "a", "bytes": [97]}])
+        )
+        .is_err());
+        assert!(serde_json::from_value::<SegmentBuffer>(json!([{"author": 1}])).is_err());
+    }
+
+    #[test]
+    fn wire_layout() {
+        // Every kind of request exactly as it is sent, including order of fields
+        let messages = [
+            r#"{"type":"do","version":1,"user":1,"time":"","operation":{"type":"insert","pos":0,"text":[{"author":1,"text":"héllo"}]}}"#,
+            r#"{"type":"do","version":1,"user":2,"time":"1:1","operation":{"type":"delete","pos":1,"len":2,"text":[{"author":1,"text":"e"},{"author":2,"text":"l"}]}}"#,
+            r#"{"type":"do","version":1,"user":2,"time":"1:1;2:1","operation":{"type":"delete","pos":0,"len":3,"recon":[{"offset":1,"text":[{"author":1,"text":"x"}]}]}}"#,
+            r#"{"type":"do","version":1,"user":1,"time":"1:1;2:2","operation":{"type":"split","first":{"type":"no-op"},"second":{"type":"delete","pos":0,"len":1}}}"#,
+            r#"{"type":"undo","version":1,"user":1,"time":"1:2;2:2"}"#,
+            r#"{"type":"redo","version":1,"user":1,"time":"1:3;2:2"}"#,
+        ];
+        for message in messages.iter() {
+            let request: Request = serde_json::from_str(message).unwrap();
+            assert_eq!(&serde_json::to_string(&request).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn undo() {
+        let request = Request::Undo(UndoRequest {
+            user: 3,
+            vector: vector(),
+        });
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            value,
+            json!({"type": "undo", "version": 1, "user": 3, "time": "1:2;3:1"})
+        );
+        let parsed: Request = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.user(), 3);
+        assert_eq!(parsed.vector(), &vector());
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let value = json!({"type": "redo", "version": 2, "user": 3, "time": ""});
+        assert!(serde_json::from_value::<Request>(value).is_err());
+    }
+
+    #[test]
+    fn mismatched_delete_len() {
+        let value = json!({
+            "type": "delete",
+            "pos": 0,
+            "len": 3,
+            "text": [{"author": 1, "text": "a"}],
+        });
+        assert!(serde_json::from_value::<Operation>(value).is_err());
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut a = State::new(SegmentBuffer::default());
+        let r1 = a.local_insert(1, 0, "hi");
+        let mut b = State::new(SegmentBuffer::default());
+        a.register(2, SessionInfo::default()).unwrap();
+        b.register(1, SessionInfo::default()).unwrap();
+        b.execute(r1);
+        a.execute(b.local_insert(2, 2, "!"));
+
+        let value = serde_json::to_value(a.snapshot()).unwrap();
+        assert_eq!(value["time"], json!("1:1;2:1"));
+        assert_eq!(
+            value["sessions"],
+            json!({
+                "1": {"name": "", "colour": 0, "present": true, "time": "1:1;2:1"},
+                "2": {"name": "", "colour": 0, "present": true, "time": "1:1;2:1"},
+            })
+        );
+        assert_eq!(value["log"].as_array().unwrap().len(), 2);
+
+        let snapshot: Snapshot = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&snapshot).unwrap(), value);
+        let c = State::from_snapshot(snapshot).unwrap();
+        assert_eq!(c.buffer, a.buffer);
+
+        let a = State::new(SegmentBuffer::from_text(NO_OWNER, "é", Unit::Chars));
+        let value = serde_json::to_value(a.snapshot()).unwrap();
+        assert_eq!(value["unit"], json!("chars"));
+        let snapshot: Snapshot = serde_json::from_value(value).unwrap();
+        assert_eq!(State::from_snapshot(snapshot).unwrap().buffer.len(), 1);
+    }
+}
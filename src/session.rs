@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use crate::{error::OtError, vector::StateVector, SessionId, NO_OWNER};
+
+/// Display information about session
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub name: String,
+    /// `0xRRGGBB`
+    pub colour: u32,
+}
+
+impl SessionInfo {
+    pub fn new(name: impl Into<String>, colour: u32) -> Self {
+        SessionInfo {
+            name: name.into(),
+            colour,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub info: SessionInfo,
+    /// Last acknowledged state of session, no future request of session can be based
+    /// on an earlier state
+    pub(crate) vector: StateVector,
+    pub(crate) present: bool,
+    pub(crate) local: bool,
+}
+
+impl Session {
+    /// Last acknowledged state of present session
+    pub fn vector(&self) -> Option<&StateVector> {
+        Some(&self.vector).filter(|_| self.present)
+    }
+    /// Session edits on this peer, so it has always seen the current state
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+    /// Departed session can't issue new requests, but its requests stay in history
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+}
+
+/// Every session, which ever joined the document, ids are never reused
+///
+/// Ids are allocated by a single authority, i.e. server, as peers allocating them
+/// independently would hand out the same ids. Other peers learn about new sessions
+/// from authority and register them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionRegistry {
+    sessions: BTreeMap<SessionId, Session>,
+}
+
+impl SessionRegistry {
+    pub fn get(&self, id: SessionId) -> Option<&Session> {
+        self.sessions.get(&id)
+    }
+
+    /// Iterates over all sessions, including departed ones, ordered by id
+    pub fn iter(&self) -> impl Iterator<Item = (SessionId, &Session)> {
+        self.sessions.iter().map(|(id, session)| (*id, session))
+    }
+
+    pub fn present(&self) -> impl Iterator<Item = (SessionId, &Session)> {
+        self.iter().filter(|(_, session)| session.present)
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn insert(&mut self, id: SessionId, session: Session) {
+        self.sessions.insert(id, session);
+    }
+
+    /// Lowest id, which was never used
+    fn allocate(&self) -> Option<SessionId> {
+        let mut id = NO_OWNER + 1;
+        for used in self.sessions.keys() {
+            if *used > id {
+                break;
+            }
+            id = used.checked_add(1)?;
+        }
+        Some(id)
+    }
+
+    pub(crate) fn join(
+        &mut self,
+        info: SessionInfo,
+        vector: StateVector,
+    ) -> Result<SessionId, OtError> {
+        let id = self.allocate().ok_or(OtError::SessionsExhausted)?;
+        self.register(id, info, vector)?;
+        Ok(id)
+    }
+
+    pub(crate) fn register(
+        &mut self,
+        id: SessionId,
+        info: SessionInfo,
+        vector: StateVector,
+    ) -> Result<(), OtError> {
+        if id == NO_OWNER || self.sessions.contains_key(&id) {
+            return Err(OtError::SessionExists(id));
+        }
+        self.sessions.insert(
+            id,
+            Session {
+                info,
+                vector,
+                present: true,
+                local: false,
+            },
+        );
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self, id: SessionId) -> Result<(), OtError> {
+        self.present_mut(id)?.present = false;
+        Ok(())
+    }
+
+    /// Session, which is allowed to issue requests
+    pub(crate) fn present_mut(&mut self, id: SessionId) -> Result<&mut Session, OtError> {
+        match self.sessions.get_mut(&id) {
+            Some(session) if session.present => Ok(session),
+            Some(_) => Err(OtError::DepartedSession(id)),
+            None => Err(OtError::UnknownSession(id)),
+        }
+    }
+
+    pub(crate) fn check(&self, id: SessionId) -> Result<(), OtError> {
+        match self.sessions.get(&id) {
+            Some(session) if session.present => Ok(()),
+            Some(_) => Err(OtError::DepartedSession(id)),
+            None => Err(OtError::UnknownSession(id)),
+        }
+    }
+
+    /// Sessions as seen by another peer, for which local sessions are remote ones, that
+    /// have seen the current state
+    pub(crate) fn exported(&self, current: &StateVector) -> SessionRegistry {
+        let mut exported = self.clone();
+        for session in exported.sessions.values_mut() {
+            if session.local {
+                session.local = false;
+                session.vector = current.clone();
+            }
+        }
+        exported
+    }
+
+    /// Last acknowledged states of remote sessions, which may still issue requests
+    pub(crate) fn known_vectors(&self) -> impl Iterator<Item = &StateVector> {
+        self.sessions
+            .values()
+            .filter(|session| session.present && !session.local)
+            .map(|session| &session.vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionInfo, SessionRegistry};
+    use crate::{error::OtError, vector::StateVector, NO_OWNER};
+
+    #[test]
+    fn allocation() {
+        let mut registry = SessionRegistry::default();
+        let info = SessionInfo::new("alice", 0xff0000);
+        assert_eq!(registry.join(info.clone(), StateVector::new()), Ok(1));
+        registry
+            .register(3, SessionInfo::default(), StateVector::new())
+            .unwrap();
+        assert_eq!(
+            registry.register(3, SessionInfo::default(), StateVector::new()),
+            Err(OtError::SessionExists(3))
+        );
+        assert_eq!(
+            registry.register(NO_OWNER, SessionInfo::default(), StateVector::new()),
+            Err(OtError::SessionExists(NO_OWNER))
+        );
+        assert_eq!(registry.join(info.clone(), StateVector::new()), Ok(2));
+
+        // Ids of departed sessions are not reused
+        registry.leave(1).unwrap();
+        assert_eq!(registry.check(1), Err(OtError::DepartedSession(1)));
+        assert_eq!(registry.check(5), Err(OtError::UnknownSession(5)));
+        assert_eq!(registry.join(info, StateVector::new()), Ok(4));
+        assert_eq!(
+            registry.present().map(|(id, _)| id).collect::<Vec<_>>(),
+            [2, 3, 4]
+        );
+        assert_eq!(registry.get(1).unwrap().info.name, "alice");
+    }
+}
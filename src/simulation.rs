@@ -0,0 +1,609 @@
+//! Deterministic convergence simulation of multiple replicas
+//!
+//! Every replica owns single session, which randomly edits its buffer, requests are
+//! delivered to other replicas in random causally consistent order. Run is fully
+//! determined by [`Config`], and recorded [`Event`]s can be replayed, so failures
+//! can be reproduced and reduced
+use std::{
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+use crate::{
+    request::Request, segment::SegmentBuffer, session::SessionInfo, Readiness, SessionId, State,
+    NO_OWNER,
+};
+
+/// Splitmix64, good enough for test input generation and stable across platforms
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..bound`, bound should not be zero
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub seed: u64,
+    pub replicas: usize,
+    /// Count of random edits and deliveries, all pending requests are delivered after
+    pub steps: usize,
+    pub initial: String,
+    /// Generate undo and redo requests too
+    pub undo: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            seed: 0,
+            replicas: 3,
+            steps: 200,
+            initial: String::new(),
+            undo: true,
+        }
+    }
+}
+
+/// Single step of simulation, replicas are numbered from zero, replica `i` owns
+/// session `i + 1`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Insert {
+        replica: usize,
+        pos: usize,
+        text: String,
+    },
+    Delete {
+        replica: usize,
+        pos: usize,
+        len: usize,
+    },
+    Undo {
+        replica: usize,
+    },
+    Redo {
+        replica: usize,
+    },
+    /// Next request of one replica is executed by another
+    Deliver {
+        from: usize,
+        to: usize,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Insert { replica, pos, text } => {
+                write!(f, "#{} insert {:?} at {}", replica, text, pos)
+            }
+            Event::Delete { replica, pos, len } => {
+                write!(f, "#{} delete {} at {}", replica, len, pos)
+            }
+            Event::Undo { replica } => write!(f, "#{} undo", replica),
+            Event::Redo { replica } => write!(f, "#{} redo", replica),
+            Event::Deliver { from, to } => write!(f, "#{} -> #{}", from, to),
+        }
+    }
+}
+
+/// Failed run, reduced to the smallest sequence of events, which still fails, see
+/// [`Simulation::replay`]
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub config: Config,
+    pub reason: String,
+    pub events: Vec<Event>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.reason)?;
+        writeln!(f, "found with {:?}, reproduced by:", self.config)?;
+        for event in &self.events {
+            writeln!(f, "  {}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failure {}
+
+struct Replica {
+    state: State,
+    session: SessionId,
+    /// Requests of other replicas, which are not delivered yet, with their origin
+    inbox: Vec<(usize, Request)>,
+}
+
+pub struct Simulation {
+    config: Config,
+    rng: Rng,
+    replicas: Vec<Replica>,
+    /// Events, which were actually applied
+    events: Vec<Event>,
+}
+
+impl Simulation {
+    pub fn new(config: Config) -> Self {
+        let initial = SegmentBuffer::from_bytes(NO_OWNER, config.initial.as_bytes());
+        let replicas = (0..config.replicas)
+            .map(|i| {
+                // Undelivered requests pile up, and every replica translates each of
+                // them to many concurrent states
+                let mut state = State::new(initial.clone()).with_cache_capacity(1 << 22);
+                for id in 1..=config.replicas {
+                    state
+                        .register(id as SessionId, SessionInfo::default())
+                        .expect("fresh registry");
+                }
+                Replica {
+                    state,
+                    session: i as SessionId + 1,
+                    inbox: Vec::new(),
+                }
+            })
+            .collect();
+        Simulation {
+            rng: Rng::new(config.seed),
+            config,
+            replicas,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn replicas(&self) -> impl Iterator<Item = &State> {
+        self.replicas.iter().map(|replica| &replica.state)
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Runs configured simulation, failure is reduced to the smallest reproducer
+    pub fn run(config: Config) -> Result<(), Failure> {
+        let failure = match Self::guard(config, |simulation| simulation.simulate()) {
+            Ok(()) => return Ok(()),
+            Err(failure) => failure,
+        };
+        Err(Self::reduce(failure))
+    }
+
+    /// Applies recorded events in order, skipping ones, which are not applicable
+    /// anymore, then delivers every pending request
+    pub fn replay(config: Config, events: &[Event]) -> Result<(), Failure> {
+        Self::guard(config, |simulation| {
+            for event in events {
+                simulation.apply(event)?;
+            }
+            simulation.finish(|_| 0)
+        })
+    }
+
+    fn guard(
+        config: Config,
+        run: impl FnOnce(&mut Simulation) -> Result<(), String>,
+    ) -> Result<(), Failure> {
+        let mut simulation = Simulation::new(config.clone());
+        let result = catch_unwind(AssertUnwindSafe(|| run(&mut simulation)));
+        let reason = match result {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(reason)) => reason,
+            Err(panic) => panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "panic".to_owned()),
+        };
+        Err(Failure {
+            config,
+            reason,
+            events: simulation.events,
+        })
+    }
+
+    /// Drops events one by one, as long as the rest still fails
+    fn reduce(mut failure: Failure) -> Failure {
+        let mut i = failure.events.len();
+        while i > 0 {
+            i -= 1;
+            let mut events = failure.events.clone();
+            events.remove(i);
+            if let Err(reduced) = Self::replay(failure.config.clone(), &events) {
+                i = i.min(reduced.events.len());
+                failure = reduced;
+            }
+        }
+        failure
+    }
+
+    fn simulate(&mut self) -> Result<(), String> {
+        for _ in 0..self.config.steps {
+            let event = if self.rng.chance(60) {
+                self.random_edit()
+            } else {
+                self.random_delivery()
+            };
+            if let Some(event) = event {
+                self.apply(&event)?;
+            }
+        }
+        let mut rng = self.rng.clone();
+        self.finish(|len| rng.below(len))
+    }
+
+    /// Delivers every pending request, choosing among ready deliveries with `pick`
+    fn finish(&mut self, mut pick: impl FnMut(usize) -> usize) -> Result<(), String> {
+        loop {
+            let deliveries = self.deliveries();
+            if deliveries.is_empty() {
+                break;
+            }
+            let (from, to) = deliveries[pick(deliveries.len())];
+            self.apply(&Event::Deliver { from, to })?;
+        }
+        if let Some((to, (from, request))) = self
+            .replicas
+            .iter()
+            .enumerate()
+            .find_map(|(i, replica)| replica.inbox.first().map(|pending| (i, pending)))
+        {
+            return Err(format!(
+                "replica #{} can't execute any pending request, oldest one of #{}: {:?}",
+                to,
+                from,
+                self.replicas[to].state.validate(request)
+            ));
+        }
+        self.check()
+    }
+
+    fn random_edit(&mut self) -> Option<Event> {
+        let replica = self.rng.below(self.replicas.len());
+        let len = self.replicas[replica].state.buffer.len();
+        let roll = self.rng.below(if self.config.undo { 100 } else { 80 });
+        Some(if roll < 50 || len == 0 {
+            let pos = self.rng.below(len + 1);
+            let text = (0..1 + self.rng.below(3))
+                .map(|_| (b'a' + self.rng.below(26) as u8) as char)
+                .collect();
+            Event::Insert { replica, pos, text }
+        } else if roll < 80 {
+            let pos = self.rng.below(len);
+            let len = 1 + self.rng.below((len - pos).min(4));
+            Event::Delete { replica, pos, len }
+        } else if roll < 92 {
+            Event::Undo { replica }
+        } else {
+            Event::Redo { replica }
+        })
+    }
+
+    fn random_delivery(&mut self) -> Option<Event> {
+        let deliveries = self.deliveries();
+        if deliveries.is_empty() {
+            return None;
+        }
+        let (from, to) = deliveries[self.rng.below(deliveries.len())];
+        Some(Event::Deliver { from, to })
+    }
+
+    /// Pending requests, which are ready to be executed by their receivers
+    fn deliveries(&self) -> Vec<(usize, usize)> {
+        let mut deliveries = Vec::new();
+        for (to, replica) in self.replicas.iter().enumerate() {
+            for (from, request) in &replica.inbox {
+                if let Ok(Readiness::Ready) = replica.state.validate(request) {
+                    deliveries.push((*from, to));
+                }
+            }
+        }
+        deliveries
+    }
+
+    /// Applies event, events which are not applicable to the current state, are
+    /// silently skipped
+    fn apply(&mut self, event: &Event) -> Result<(), String> {
+        let request =
+            match *event {
+                Event::Insert {
+                    replica,
+                    pos,
+                    ref text,
+                } => {
+                    let replica = self.replicas.get_mut(replica).ok_or("no such replica")?;
+                    if pos > replica.state.buffer.len() {
+                        return Ok(());
+                    }
+                    replica.state.local_insert(replica.session, pos, text)
+                }
+                Event::Delete { replica, pos, len } => {
+                    let replica = self.replicas.get_mut(replica).ok_or("no such replica")?;
+                    if len == 0 || pos + len > replica.state.buffer.len() {
+                        return Ok(());
+                    }
+                    replica.state.local_delete(replica.session, pos, len)
+                }
+                Event::Undo { replica } => {
+                    let replica = self.replicas.get_mut(replica).ok_or("no such replica")?;
+                    match replica.state.undo(replica.session) {
+                        Some(request) => request,
+                        None => return Ok(()),
+                    }
+                }
+                Event::Redo { replica } => {
+                    let replica = self.replicas.get_mut(replica).ok_or("no such replica")?;
+                    match replica.state.redo(replica.session) {
+                        Some(request) => request,
+                        None => return Ok(()),
+                    }
+                }
+                Event::Deliver { from, to } => {
+                    let replica = self.replicas.get_mut(to).ok_or("no such replica")?;
+                    // Requests of single replica can only be executed in order, so at
+                    // most one of them is ready
+                    let idx = match replica.inbox.iter().position(|(origin, request)| {
+                        *origin == from
+                            && matches!(replica.state.validate(request), Ok(Readiness::Ready))
+                    }) {
+                        Some(idx) => idx,
+                        None => return Ok(()),
+                    };
+                    let (_, request) = replica.inbox.remove(idx);
+                    self.events.push(event.clone());
+                    return replica.state.try_execute(request).map(|_| ()).map_err(|e| {
+                        format!("replica #{} rejected request of #{}: {}", to, from, e)
+                    });
+                }
+            };
+        let origin = match *event {
+            Event::Insert { replica, .. }
+            | Event::Delete { replica, .. }
+            | Event::Undo { replica }
+            | Event::Redo { replica } => replica,
+            Event::Deliver { .. } => unreachable!(),
+        };
+        self.events.push(event.clone());
+        for (i, replica) in self.replicas.iter_mut().enumerate() {
+            if i != origin {
+                replica.inbox.push((origin, request.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let first = &self.replicas[0].state;
+        for (i, replica) in self.replicas.iter().enumerate().skip(1) {
+            if replica.state.buffer != first.buffer {
+                return Err(format!(
+                    "replicas #0 and #{} diverged: {:?} != {:?}",
+                    i, first.buffer, replica.state.buffer
+                ));
+            }
+            if replica.state.vector() != first.vector() {
+                return Err(format!(
+                    "replicas #0 and #{} are at different states: {} != {}",
+                    i,
+                    first.vector(),
+                    replica.state.vector()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Event, Rng, Simulation};
+    use proptest::prelude::*;
+
+    fn insert(replica: usize, pos: usize, text: &str) -> Event {
+        Event::Insert {
+            replica,
+            pos,
+            text: text.to_owned(),
+        }
+    }
+
+    fn delete(replica: usize, pos: usize, len: usize) -> Event {
+        Event::Delete { replica, pos, len }
+    }
+
+    fn deliver(from: usize, to: usize) -> Event {
+        Event::Deliver { from, to }
+    }
+
+    #[test]
+    fn rng_is_deterministic() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    proptest! {
+        // Every case is a whole simulation, so there are fewer of them, and runs are
+        // shorter than by default
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn replicas_converge(seed in any::<u64>(), replicas in 2usize..=4, undo in any::<bool>()) {
+            let config = Config {
+                seed,
+                replicas,
+                steps: 40,
+                initial: "hello world".to_owned(),
+                undo,
+            };
+            if let Err(failure) = Simulation::run(config) {
+                prop_assert!(false, "{}", failure);
+            }
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let config = Config {
+            replicas: 2,
+            initial: "ab".to_owned(),
+            ..Config::default()
+        };
+        let events = vec![
+            insert(0, 1, "x"),
+            delete(1, 0, 2),
+            // Not applicable, buffer is empty
+            delete(1, 0, 1),
+            deliver(1, 0),
+        ];
+        assert!(Simulation::replay(config, &events).is_ok());
+    }
+
+    fn three_replicas() -> Config {
+        Config {
+            replicas: 3,
+            initial: "hello world".to_owned(),
+            ..Config::default()
+        }
+    }
+
+    /// Inserts collapsed by concurrent delete are ordered by characters, they were
+    /// inserted after
+    #[test]
+    fn colliding_inserts_in_deleted_range() {
+        let events = vec![
+            insert(1, 5, "cah"),
+            delete(2, 4, 4),
+            insert(0, 4, "ozr"),
+            insert(2, 4, "k"),
+            deliver(1, 0),
+            deliver(2, 0),
+            deliver(0, 2),
+            deliver(2, 0),
+            deliver(2, 1),
+            deliver(1, 2),
+            deliver(2, 1),
+            deliver(0, 1),
+        ];
+        if let Err(failure) = Simulation::replay(three_replicas(), &events) {
+            panic!("{}", failure);
+        }
+    }
+
+    /// Undo must be mirrored after its associated request is translated past
+    /// concurrent delete, undo transformed against that delete as plain insert
+    /// restores text, which another replica deleted
+    #[test]
+    fn undo_of_overlapping_delete() {
+        let events = vec![
+            delete(1, 4, 3),
+            insert(2, 1, "jx"),
+            delete(0, 4, 2),
+            deliver(0, 2),
+            delete(2, 2, 1),
+            Event::Undo { replica: 0 },
+            deliver(2, 0),
+            deliver(1, 2),
+            deliver(0, 1),
+            deliver(2, 1),
+            deliver(0, 1),
+            deliver(0, 2),
+            deliver(2, 1),
+            deliver(1, 0),
+            deliver(2, 0),
+        ];
+        if let Err(failure) = Simulation::replay(three_replicas(), &events) {
+            panic!("{}", failure);
+        }
+    }
+
+    /// Inserts collapsed by concurrent deletes, reduced from seed 220 with 4 replicas
+    #[test]
+    fn inserts_collapsed_by_deletes() {
+        let config = Config {
+            replicas: 4,
+            initial: "hello world".to_owned(),
+            undo: false,
+            ..Config::default()
+        };
+        let events = vec![
+            delete(1, 1, 3),
+            delete(1, 0, 4),
+            insert(1, 0, "ow"),
+            insert(0, 1, "u"),
+            insert(2, 3, "n"),
+            deliver(0, 1),
+            deliver(2, 1),
+            deliver(1, 3),
+            deliver(1, 3),
+            deliver(1, 3),
+            deliver(0, 3),
+            deliver(2, 3),
+        ];
+        if let Err(failure) = Simulation::replay(config, &events) {
+            panic!("{}", failure);
+        }
+    }
+
+    /// Undo of delete, which shrank by concurrent delete, reduced from seed 388 with
+    /// 3 replicas, revived text is placed by characters, which are still revived
+    #[test]
+    fn undo_of_shrunk_delete() {
+        let events = vec![
+            delete(2, 7, 4),
+            insert(1, 10, "b"),
+            insert(1, 5, "a"),
+            insert(0, 6, "sa"),
+            delete(0, 6, 4),
+            deliver(2, 1),
+            deliver(0, 1),
+            insert(1, 11, "ilh"),
+            Event::Undo { replica: 2 },
+        ];
+        if let Err(failure) = Simulation::replay(three_replicas(), &events) {
+            panic!("{}", failure);
+        }
+    }
+
+    /// Insert after character, which is revived by concurrent undo, splits revived
+    /// text, reduced from seed 64 with 3 replicas
+    #[test]
+    fn insert_into_revived_text() {
+        let events = vec![
+            delete(2, 4, 4),
+            insert(0, 5, "z"),
+            Event::Undo { replica: 0 },
+            deliver(2, 1),
+            delete(1, 3, 2),
+            Event::Undo { replica: 2 },
+            deliver(0, 1),
+            delete(1, 2, 1),
+            insert(1, 3, "a"),
+        ];
+        if let Err(failure) = Simulation::replay(three_replicas(), &events) {
+            panic!("{}", failure);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    error::OtError, request::Request, segment::SegmentBuffer, session::SessionRegistry,
+    vector::StateVector, State,
+};
+
+/// Everything, joining peer needs to continue editing, see [`State::snapshot`]
+#[derive(Clone)]
+pub struct Snapshot {
+    pub buffer: SegmentBuffer,
+    pub vector: StateVector,
+    /// Requests in causal order, which are still needed to translate future requests
+    /// of existing sessions, or to undo them
+    pub log: Vec<Request>,
+    pub sessions: SessionRegistry,
+}
+
+impl State {
+    pub fn snapshot(&self) -> Snapshot {
+        let bound = self.garbage_bound();
+        Snapshot {
+            buffer: self.buffer.clone(),
+            vector: self.vector.clone(),
+            log: self
+                .log
+                .iter()
+                .filter(|r| r.vector().get(r.user()) >= bound.get(r.user()))
+                .cloned()
+                .collect(),
+            sessions: self.sessions.exported(&self.vector),
+        }
+    }
+
+    /// Restores state from snapshot, which should be created by [`State::snapshot`],
+    /// undo limit is not part of snapshot, see [`State::with_max_undo`]
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<State, OtError> {
+        let mut state = State::new(snapshot.buffer);
+        state.vector = snapshot.vector;
+        for request in snapshot.log {
+            let (user, n) = (request.user(), request.vector().get(request.user()));
+            let follows = match state.log.last(user) {
+                Some(last) => last.vector().get(user).checked_add(1) == Some(n),
+                None => true,
+            };
+            if !follows
+                || n >= state.vector.get(user)
+                || !request.vector().casually_before(&state.vector)
+            {
+                return Err(OtError::InvalidSnapshot);
+            }
+            state.log.push(state.in_unit(&request)?.into_owned());
+        }
+        // Only the oldest requests of session could be omitted
+        for session in state.vector.sessions() {
+            if let Some(last) = state.log.last(session) {
+                if last.vector().get(session).checked_add(1) != Some(state.vector.get(session)) {
+                    return Err(OtError::InvalidSnapshot);
+                }
+            }
+        }
+        state.sessions = snapshot.sessions;
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::OtError,
+        op::Insert,
+        request::{dor::DoRequest, Request},
+        segment::SegmentBuffer,
+        session::SessionInfo,
+        State, NO_OWNER,
+    };
+
+    #[test]
+    fn late_join() {
+        let initial = SegmentBuffer::from_bytes(NO_OWNER, b"abc");
+        let mut a = State::new(initial.clone()).with_authority(true);
+        let mut b = State::new(initial);
+        a.register(2, SessionInfo::default()).unwrap();
+        b.register(1, SessionInfo::default()).unwrap();
+
+        let r1 = a.local_insert(1, 3, "d");
+        b.execute(r1);
+        let r2 = b.local_delete(2, 0, 1);
+        a.execute(r2);
+        // Not yet seen by a, so it is concurrent to everything joining peer knows
+        let r3 = b.local_insert(2, 3, "e");
+        let r4 = a.local_insert(1, 0, "x");
+
+        let id = a.join(SessionInfo::new("carol", 0x00ff00)).unwrap();
+        assert_eq!(id, 3);
+        b.register(id, SessionInfo::new("carol", 0x00ff00)).unwrap();
+        let mut c = State::from_snapshot(a.snapshot()).unwrap();
+        assert_eq!(c.buffer, a.buffer);
+        assert!(c.can_undo(1));
+        assert_eq!(c.sessions().get(id).unwrap().info.name, "carol");
+        // Author of snapshot has seen everything, it contains
+        assert_eq!(c.sessions().get(1).unwrap().vector(), Some(a.vector()));
+        assert!(!c.sessions().get(1).unwrap().is_local());
+        assert_eq!(
+            c.join(SessionInfo::default()).err(),
+            Some(OtError::NotAuthority)
+        );
+
+        a.execute(r3.clone());
+        c.execute(r3);
+        b.execute(r4);
+        let r5 = c.local_insert(id, 1, "y");
+        a.execute(r5.clone());
+        b.execute(r5);
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(a.buffer, c.buffer);
+    }
+
+    #[test]
+    fn invalid_snapshot() {
+        let mut a = State::new(SegmentBuffer::default());
+        a.local_insert(1, 0, "a");
+        a.local_insert(1, 1, "b");
+
+        let mut snapshot = a.snapshot();
+        snapshot.log.remove(1);
+        assert_eq!(
+            State::from_snapshot(snapshot).err(),
+            Some(OtError::InvalidSnapshot)
+        );
+    }
+
+    #[test]
+    fn count_overflow() {
+        let mut a = State::new(SegmentBuffer::default());
+        a.register(1, SessionInfo::default()).unwrap();
+        let mut snapshot = a.snapshot();
+        snapshot.vector.set(1, usize::MAX);
+        let mut b = State::from_snapshot(snapshot).unwrap();
+
+        let insert = Insert::new(0, SegmentBuffer::from_bytes(1, b"a"));
+        let request = Request::Do(DoRequest::new(1, b.vector().clone(), insert.into()));
+        assert_eq!(
+            b.try_execute(request).err(),
+            Some(OtError::VectorOverflow(1))
+        );
+        assert_eq!(b.vector().get(1), usize::MAX);
+        assert_eq!(b.buffer, SegmentBuffer::default());
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+};
+
+use crate::{
+    error::OtError, request::Request, vector::StateVector, ConcurrentOrder, SessionId, State,
+};
+
+/// Request is identified by its issuer and its own component of vector, which is only
+/// unique for logged requests, so translations of other ones are never cached
+type TranslationKey = (SessionId, usize, StateVector);
+
+fn key(request: &Request, target: &StateVector) -> TranslationKey {
+    (
+        request.user(),
+        request.vector().get(request.user()),
+        target.clone(),
+    )
+}
+
+/// Already translated requests, oldest entries are evicted once capacity is exceeded
+pub(crate) struct TranslationCache {
+    entries: HashMap<TranslationKey, Request>,
+    /// Insertion order, may contain keys of already removed entries
+    order: VecDeque<TranslationKey>,
+    capacity: usize,
+}
+
+impl TranslationCache {
+    pub fn new(capacity: usize) -> Self {
+        TranslationCache {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn get(&self, key: &TranslationKey) -> Option<&Request> {
+        self.entries.get(key)
+    }
+
+    fn insert(&mut self, key: TranslationKey, request: Request) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key.clone(), request).is_none() {
+            self.order.push_back(key);
+        }
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(key) => self.entries.remove(&key),
+                None => break,
+            };
+        }
+        if self.order.len() > self.entries.len() * 2 {
+            let entries = &self.entries;
+            self.order.retain(|key| entries.contains_key(key));
+        }
+    }
+
+    /// Drops translations of requests, which were issued before bound
+    pub fn forget_before(&mut self, bound: &StateVector) {
+        self.entries.retain(|(u, n, _), _| *n >= bound.get(*u));
+    }
+}
+
+/// How request is translated, once translations it depends on are known
+enum Step<'a> {
+    /// Undo/redo is mirrored associated request
+    Mirror {
+        assoc: &'a Request,
+        at: StateVector,
+        by: usize,
+    },
+    /// Undo/redo of another session is skipped together with its associated request
+    Fold {
+        at: StateVector,
+        session: SessionId,
+        by: usize,
+    },
+    /// Request is transformed against last request of another session
+    Transform {
+        at: StateVector,
+        last_request: &'a Request,
+    },
+}
+
+#[cfg(feature = "tracing")]
+impl Step<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Mirror { .. } => "mirror",
+            Step::Fold { .. } => "fold",
+            Step::Transform { .. } => "transform",
+        }
+    }
+}
+
+enum Plan<'a> {
+    Done(Request),
+    Step(Step<'a>),
+}
+
+struct Frame<'a> {
+    request: &'a Request,
+    target: StateVector,
+    step: Step<'a>,
+}
+
+/// Translations done during single [`State::try_translate`] call, which are kept
+/// regardless of cache eviction
+type Translated = HashMap<TranslationKey, Result<Request, OtError>>;
+
+fn lookup<'d>(
+    done: &'d Translated,
+    request: &Request,
+    at: &StateVector,
+) -> Result<&'d Request, OtError> {
+    match done.get(&key(request, at)) {
+        Some(Ok(request)) => Ok(request),
+        Some(Err(e)) => Err(e.clone()),
+        None => Err(OtError::NoTranslationPath),
+    }
+}
+
+impl State {
+    /// Translates request using explicit work stack instead of recursion, so long
+    /// histories can't overflow the stack, every intermediate translation is done once
+    pub(crate) fn translate_iterative(
+        &self,
+        request: &Request,
+        target: &StateVector,
+    ) -> Result<Request, OtError> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "translate",
+            session = request.user(),
+            from = %request.vector(),
+            to = %target,
+            max_depth = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        #[cfg(feature = "tracing")]
+        let mut max_depth = 0;
+        let root = key(request, target);
+        let mut done = Translated::new();
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut in_progress = HashSet::new();
+        let mut pending = Some((request, target.clone()));
+
+        loop {
+            if let Some((request, target)) = pending.take() {
+                let k = key(request, &target);
+                let cached = match self.is_logged(request) {
+                    true => self.translated.borrow().get(&k).cloned(),
+                    false => None,
+                };
+                if let Some(translated) = cached {
+                    done.insert(k, Ok(translated));
+                } else if in_progress.contains(&k) {
+                    // Translation depends on itself
+                    done.insert(k, Err(OtError::NoTranslationPath));
+                } else {
+                    match self.plan(request, &target) {
+                        Ok(Plan::Done(translated)) => {
+                            done.insert(k, Ok(translated));
+                        }
+                        Ok(Plan::Step(step)) => {
+                            #[cfg(feature = "tracing")]
+                            {
+                                max_depth = max_depth.max(stack.len() + 1);
+                                tracing::trace!(
+                                    depth = stack.len() + 1,
+                                    session = request.user(),
+                                    from = %request.vector(),
+                                    to = %target,
+                                    step = step.name(),
+                                    "step",
+                                );
+                            }
+                            in_progress.insert(k);
+                            stack.push(Frame {
+                                request,
+                                target,
+                                step,
+                            });
+                        }
+                        Err(e) => {
+                            done.insert(k, Err(e));
+                        }
+                    }
+                }
+            }
+
+            let frame = match stack.last() {
+                Some(frame) => frame,
+                None => break,
+            };
+            if let Some(dependency) = self.next_dependency(frame, &done) {
+                pending = Some(dependency);
+                continue;
+            }
+            let frame = stack.pop().unwrap();
+            let k = key(frame.request, &frame.target);
+            in_progress.remove(&k);
+            let logged = self.is_logged(frame.request);
+            let result = self.finish(frame, &done);
+            if let (true, Ok(translated)) = (logged, &result) {
+                self.translated
+                    .borrow_mut()
+                    .insert(k.clone(), translated.clone());
+            }
+            done.insert(k, result);
+        }
+
+        #[cfg(feature = "tracing")]
+        span.record("max_depth", max_depth);
+        done.remove(&root)
+            .unwrap_or(Err(OtError::NoTranslationPath))
+    }
+
+    /// Request is the logged one, not just a request with the same key
+    fn is_logged(&self, request: &Request) -> bool {
+        let (user, n) = (request.user(), request.vector().get(request.user()));
+        matches!(self.log.get(user, n), Some(logged) if std::ptr::eq(logged, request))
+    }
+
+    /// Chooses how to translate request, without translating anything yet
+    fn plan<'a>(&'a self, request: &'a Request, target: &StateVector) -> Result<Plan<'a>, OtError> {
+        match request {
+            Request::Do(dor) if &dor.vector == target => {
+                return Ok(Plan::Done(Request::Do(dor.clone())))
+            }
+            Request::Undo(_) | Request::Redo(_) => {
+                if let Some(assoc) = self.associated_request(request) {
+                    let mut mirror_at = target.clone();
+                    mirror_at.set(request.user(), assoc.vector().get(request.user()));
+
+                    if self.reachable(&mirror_at) {
+                        let by = target
+                            .get(request.user())
+                            .checked_sub(mirror_at.get(request.user()))
+                            .ok_or(OtError::VectorUnderflow(request.user()))?;
+                        return Ok(Plan::Step(Step::Mirror {
+                            assoc,
+                            at: mirror_at,
+                            by,
+                        }));
+                    }
+                }
+            }
+            _ => {}
+        };
+
+        let base = self.base(request);
+        let reverted = match request {
+            Request::Do(_) => None,
+            _ => self.associated_request(request),
+        };
+        let mut transform = None;
+        for session in self.vector.sessions() {
+            if session == request.user() {
+                continue;
+            }
+            if target.get(session) <= base.get(session) {
+                continue;
+            }
+            let last_request = self
+                .log
+                .get(session, target.get(session) - 1)
+                .ok_or(OtError::NoTranslationPath)?;
+
+            if let Request::Undo(_) | Request::Redo(_) = last_request {
+                // Undo/redo together with its associated request can be skipped,
+                // as they cancel each other out
+                let assoc = self
+                    .associated_request(last_request)
+                    .ok_or(OtError::NoAssociatedRequest)?;
+                let fold_by = target
+                    .get(session)
+                    .checked_sub(assoc.vector().get(session))
+                    .ok_or(OtError::VectorUnderflow(session))?;
+
+                // Associated request may be the first one of session, then its
+                // component is folded down to zero
+                let fold_at = {
+                    let mut nv = target.clone();
+                    nv.remove(session, fold_by);
+                    nv
+                };
+                if self.reachable(&fold_at) && base.casually_before(&fold_at) {
+                    return Ok(Plan::Step(Step::Fold {
+                        at: fold_at,
+                        session,
+                        by: fold_by,
+                    }));
+                }
+            }
+
+            let transform_at = {
+                let mut value = target.clone();
+                value.remove(session, 1);
+                value
+            };
+            if !self.reachable(&transform_at) {
+                continue;
+            }
+            // Undo/redo is only inverse of its associated request, when it is mirrored,
+            // so requests, which prevent mirroring, are transformed against first, and
+            // undo/redo of other sessions last, as they may become foldable
+            let priority = match last_request {
+                Request::Do(_) => match reverted {
+                    Some(reverted)
+                        if last_request.vector().get(request.user())
+                            > reverted.vector().get(request.user()) =>
+                    {
+                        0
+                    }
+                    _ => 1,
+                },
+                _ => 2,
+            };
+            if transform.as_ref().is_none_or(|(best, _)| priority < *best) {
+                transform = Some((
+                    priority,
+                    Step::Transform {
+                        at: transform_at,
+                        last_request,
+                    },
+                ));
+            }
+        }
+
+        transform
+            .map(|(_, step)| Plan::Step(step))
+            .ok_or(OtError::NoTranslationPath)
+    }
+
+    /// First translation, frame depends on, which is not done yet
+    fn next_dependency<'a>(
+        &self,
+        frame: &Frame<'a>,
+        done: &Translated,
+    ) -> Option<(&'a Request, StateVector)> {
+        let missing = |request: &'a Request, at: &StateVector| match done.get(&key(request, at)) {
+            None => Some(Some((request, at.clone()))),
+            // Frame fails anyway
+            Some(Err(_)) => Some(None),
+            Some(Ok(_)) => None,
+        };
+        let required = match &frame.step {
+            Step::Mirror { assoc, at, .. } => missing(assoc, at),
+            Step::Fold { at, .. } => missing(frame.request, at),
+            Step::Transform { at, last_request } => {
+                missing(frame.request, at).or_else(|| missing(last_request, at))
+            }
+        };
+        required.flatten()
+    }
+
+    fn finish(&self, frame: Frame, done: &Translated) -> Result<Request, OtError> {
+        let request = frame.request;
+        match frame.step {
+            Step::Mirror { assoc, at, by } => lookup(done, assoc, &at)?.try_mirror(by),
+            Step::Fold { at, session, by } => Ok(lookup(done, request, &at)?.fold(session, by)),
+            Step::Transform { at, last_request } => {
+                let r1 = lookup(done, request, &at)?;
+                let r2 = lookup(done, last_request, &at)?;
+                // Order of inserted text is the same at every site
+                if let (Some(op1), Some(op2)) = (r1.operation(), r2.operation()) {
+                    let merged = self.merge_insertions(&at, (request, op1), (last_request, op2));
+                    if let Ok(Some(operation)) = merged {
+                        return r1.transformed(r2, operation);
+                    }
+                }
+                r1.try_transform(r2, self.cid(r1, r2))
+            }
+        }
+    }
+
+    /// Associated request and state, at which it is mirrored, when undo/redo is
+    /// translated to target, later steps don't change text inserted by undo/redo
+    pub(crate) fn mirrored_at<'a>(
+        &'a self,
+        request: &'a Request,
+        target: &StateVector,
+    ) -> Result<(&'a Request, StateVector), OtError> {
+        let mut target = target.clone();
+        loop {
+            target = match self.plan(request, &target)? {
+                Plan::Step(Step::Mirror { assoc, at, .. }) => return Ok((assoc, at)),
+                Plan::Step(Step::Fold { at, .. }) | Plan::Step(Step::Transform { at, .. }) => at,
+                Plan::Done(_) => return Err(OtError::NotTranslated),
+            };
+        }
+    }
+
+    /// Earliest state, request can be translated to, undo/redo reverts its associated
+    /// request, so it may be translated back to any state, at which the associated
+    /// request is known
+    fn base(&self, request: &Request) -> StateVector {
+        let mut base = match request {
+            Request::Do(_) => return request.vector().clone(),
+            _ => match self.associated_request(request) {
+                Some(assoc) => assoc.vector().clone(),
+                None => return request.vector().clone(),
+            },
+        };
+        base.set(request.user(), request.vector().get(request.user()));
+        base
+    }
+
+    /// Decides which of two concurrent translated requests should be shifted, when
+    /// it is not possible to tell by their current positions
+    fn cid(&self, r1: &Request, r2: &Request) -> Option<ConcurrentOrder> {
+        let (op1, op2) = (r1.operation()?, r2.operation()?);
+        if !op1.requires_cid() {
+            return None;
+        }
+        if let Some(cid) = op1.cid(op2) {
+            return Some(cid);
+        }
+
+        // Last resort, specified by infinote protocol
+        match r1.user().cmp(&r2.user()) {
+            Ordering::Less => Some(ConcurrentOrder::This),
+            Ordering::Greater => Some(ConcurrentOrder::Other),
+            Ordering::Equal => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key, Plan, Step, TranslationCache};
+    use crate::{
+        op::Operation,
+        request::{dor::DoRequest, Request},
+        segment::SegmentBuffer,
+        session::SessionInfo,
+        vector::StateVector,
+        State, DEFAULT_CACHE_CAPACITY,
+    };
+
+    fn converge(n: usize, capacity: usize) {
+        let mut a = State::new(SegmentBuffer::default()).with_cache_capacity(capacity);
+        let mut b = State::new(SegmentBuffer::default()).with_cache_capacity(capacity);
+        a.register(2, SessionInfo::default()).unwrap();
+        b.register(1, SessionInfo::default()).unwrap();
+        let ra = (0..n)
+            .map(|i| a.local_insert(1, i, "a"))
+            .collect::<Vec<_>>();
+        let rb = (0..n)
+            .map(|i| b.local_insert(2, i / 2, "b"))
+            .collect::<Vec<_>>();
+        for r in rb {
+            a.execute(r);
+        }
+        for r in ra {
+            b.execute(r);
+        }
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(a.buffer.len(), n * 2);
+    }
+
+    #[test]
+    fn long_concurrent_history() {
+        converge(100, DEFAULT_CACHE_CAPACITY);
+    }
+
+    #[test]
+    fn tiny_cache() {
+        converge(12, 2);
+        converge(12, 0);
+    }
+
+    #[test]
+    fn cache_eviction() {
+        let request = |n: usize| {
+            let mut vector = StateVector::new();
+            vector.set(1, n);
+            Request::Do(DoRequest::new(1, vector, Operation::NoOp))
+        };
+        let target = StateVector::new();
+        let mut cache = TranslationCache::new(2);
+        for n in 0..3 {
+            cache.insert(key(&request(n), &target), request(n));
+        }
+        assert!(cache.get(&key(&request(0), &target)).is_none());
+        assert!(cache.get(&key(&request(2), &target)).is_some());
+        let mut bound = StateVector::new();
+        bound.set(1, 2);
+        cache.forget_before(&bound);
+        assert!(cache.get(&key(&request(1), &target)).is_none());
+        assert!(cache.get(&key(&request(2), &target)).is_some());
+    }
+
+    #[test]
+    fn fold_to_first_request() {
+        let mut a = State::new(SegmentBuffer::default());
+        let mut b = State::new(SegmentBuffer::default());
+        a.register(2, SessionInfo::default()).unwrap();
+        b.register(1, SessionInfo::default()).unwrap();
+        let r1 = a.local_insert(1, 0, "a");
+        let r2 = b.local_insert(2, 0, "b");
+        let r3 = b.undo(2).unwrap();
+
+        // Undo of the first request of session is folded along with it
+        assert!(matches!(
+            b.plan(&r1, b.vector()),
+            Ok(Plan::Step(Step::Fold { by: 2, .. }))
+        ));
+        b.execute(r1);
+        a.execute(r2);
+        a.execute(r3);
+        assert_eq!(a.buffer, b.buffer);
+        assert_eq!(a.buffer, SegmentBuffer::from_bytes(1, b"a"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{fmt, str::FromStr};
+
+use crate::{error::OtError, TextPosition, TextSize};
+
+/// What text positions and lengths are counted in
+///
+/// Buffer is always stored as UTF-8, for units other than [`Unit::Bytes`] it should be
+/// valid UTF-8, and positions never point inside of code point
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Unit {
+    /// Raw bytes, text is not required to be valid UTF-8
+    #[default]
+    Bytes,
+    /// Unicode scalar values, as used by infinote
+    Chars,
+    /// UTF-16 code units, as used by JavaScript and most editors
+    Utf16,
+}
+
+impl Unit {
+    pub const ALL: [Unit; 3] = [Unit::Bytes, Unit::Chars, Unit::Utf16];
+
+    /// Count of units in code point, starting with this byte
+    fn units(self, byte: u8) -> TextSize {
+        match self {
+            Unit::Bytes => 1,
+            _ if is_continuation(byte) => 0,
+            Unit::Utf16 if byte >= 0xf0 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Length of UTF-8 encoded data
+    pub fn len(self, data: &[u8]) -> TextSize {
+        match self {
+            Unit::Bytes => data.len(),
+            _ => data.iter().map(|b| self.units(*b)).sum(),
+        }
+    }
+
+    /// Byte offset of position in UTF-8 encoded data
+    pub fn offset(self, data: &[u8], pos: TextPosition) -> Result<usize, OtError> {
+        if self == Unit::Bytes {
+            return if pos <= data.len() {
+                Ok(pos)
+            } else {
+                Err(OtError::OutOfRange {
+                    end: pos,
+                    len: data.len(),
+                })
+            };
+        }
+        let mut units = 0;
+        for (i, byte) in data.iter().enumerate() {
+            if is_continuation(*byte) {
+                continue;
+            }
+            if units == pos {
+                return Ok(i);
+            } else if units > pos {
+                return Err(OtError::InsideCodePoint(pos));
+            }
+            units += self.units(*byte);
+        }
+        if units == pos {
+            Ok(data.len())
+        } else if units > pos {
+            Err(OtError::InsideCodePoint(pos))
+        } else {
+            Err(OtError::OutOfRange {
+                end: pos,
+                len: units,
+            })
+        }
+    }
+
+    /// Fails if units of data can't be counted
+    pub fn validate(self, data: &[u8]) -> Result<(), OtError> {
+        match self {
+            Unit::Bytes => Ok(()),
+            _ => std::str::from_utf8(data)
+                .map(|_| ())
+                .map_err(|_| OtError::InvalidUtf8),
+        }
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Whether byte offset in UTF-8 encoded data is not inside of code point
+pub(crate) fn is_boundary(data: &[u8], offset: usize) -> bool {
+    data.get(offset).is_none_or(|byte| !is_continuation(*byte))
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Unit::Bytes => "bytes",
+            Unit::Chars => "chars",
+            Unit::Utf16 => "utf16",
+        })
+    }
+}
+
+impl FromStr for Unit {
+    type Err = OtError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Unit::ALL
+            .iter()
+            .copied()
+            .find(|unit| unit.to_string() == s)
+            .ok_or_else(|| OtError::UnknownUnit(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Unit;
+    use crate::error::OtError;
+
+    const TEXT: &str = "aé€😀b";
+
+    #[test]
+    fn lengths() {
+        let data = TEXT.as_bytes();
+        assert_eq!(Unit::Bytes.len(data), 11);
+        assert_eq!(Unit::Chars.len(data), 5);
+        assert_eq!(Unit::Utf16.len(data), 6);
+    }
+
+    #[test]
+    fn offsets() {
+        let data = TEXT.as_bytes();
+        assert_eq!(Unit::Chars.offset(data, 3), Ok(6));
+        assert_eq!(Unit::Chars.offset(data, 5), Ok(11));
+        assert_eq!(Unit::Utf16.offset(data, 5), Ok(10));
+        assert_eq!(
+            Unit::Utf16.offset(data, 4),
+            Err(OtError::InsideCodePoint(4))
+        );
+        assert_eq!(
+            Unit::Chars.offset(data, 6),
+            Err(OtError::OutOfRange { end: 6, len: 5 })
+        );
+    }
+
+    #[test]
+    fn parse() {
+        for unit in Unit::ALL.iter() {
+            assert_eq!(unit.to_string().parse(), Ok(*unit));
+        }
+        assert!("words".parse::<Unit>().is_err());
+    }
+}
@@ -1,82 +1,363 @@
 use crate::SessionId;
-use std::ops::AddAssign;
+use smallvec::SmallVec;
+use std::{cmp::Ordering, fmt, ops::AddAssign, str::FromStr};
 
-#[derive(PartialEq, Eq, Debug, Clone)]
-pub struct StateVector(Vec<usize>);
+/// Sparse vector of per-session request counts
+///
+/// Stored as `(session, count)` pairs sorted by session, zero counts are never stored,
+/// so vectors with the same non-zero components are always equal
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct StateVector(SmallVec<[(SessionId, usize); 4]>);
 
 impl StateVector {
     pub fn new() -> Self {
         Default::default()
     }
+    fn position(&self, u: SessionId) -> Result<usize, usize> {
+        self.0.binary_search_by_key(&u, |(s, _)| *s)
+    }
+    /// # Panics
+    ///
+    /// If component overflows, see [`StateVector::checked_add`]
     pub fn add(&mut self, u: SessionId, v: usize) {
-        if self.0.len() < u as usize {
-            self.0.resize_with(u as usize + 1, Default::default)
-        }
-        self.0[u as usize] += v;
+        self.checked_add(u, v).expect("overflow");
     }
+    /// Increments component, returns its new value, or [`None`] if it overflows, in
+    /// which case vector is left unchanged
+    pub fn checked_add(&mut self, u: SessionId, v: usize) -> Option<usize> {
+        let value = self.get(u).checked_add(v)?;
+        self.set(u, value);
+        Some(value)
+    }
+    /// # Panics
+    ///
+    /// If component is less than removed value, see [`StateVector::checked_remove`]
     pub fn remove(&mut self, u: SessionId, v: usize) {
-        self.0[u as usize] = self.0[u as usize].checked_sub(v).expect("underflow");
+        self.checked_remove(u, v).expect("underflow");
+    }
+    /// Decrements component, returns its new value, or [`None`] if it is less than
+    /// removed value, in which case vector is left unchanged
+    pub fn checked_remove(&mut self, u: SessionId, v: usize) -> Option<usize> {
+        let value = self.get(u).checked_sub(v)?;
+        self.set(u, value);
+        Some(value)
     }
     pub fn get(&self, u: SessionId) -> usize {
-        if let Some(v) = self.0.get(u as usize) {
-            *v
-        } else {
-            0
+        match self.position(u) {
+            Ok(i) => self.0[i].1,
+            Err(_) => 0,
         }
     }
     pub fn set(&mut self, u: SessionId, value: usize) {
-        if self.0.len() < u as usize {
-            self.0.resize_with(u as usize + 1, Default::default)
+        match (self.position(u), value) {
+            (Ok(i), 0) => {
+                self.0.remove(i);
+            }
+            (Ok(i), value) => self.0[i].1 = value,
+            (Err(_), 0) => {}
+            (Err(i), value) => self.0.insert(i, (u, value)),
         }
-        self.0[u as usize] = value;
     }
     pub fn casually_before(&self, other: &Self) -> bool {
-        self.0
-            .iter()
-            .enumerate()
-            .all(|(u, v)| *v <= other.get(u as SessionId))
+        self.0.iter().all(|(u, v)| *v <= other.get(*u))
     }
+    /// Iterates over sessions with non-zero counts, ordered by session
     pub fn iter(&self) -> impl Iterator<Item = (SessionId, &usize)> {
-        self.0.iter().enumerate().map(|(u, v)| (u as SessionId, v))
+        self.0.iter().map(|(u, v)| (*u, v))
     }
-    pub fn sessions(&self) -> impl Iterator<Item = SessionId> {
-        (0..self.0.len()).map(|v| v as SessionId)
+    pub fn sessions(&self) -> impl Iterator<Item = SessionId> + '_ {
+        self.0.iter().map(|(u, _)| *u)
     }
 }
 
 impl StateVector {
+    /// Least common successor, component-wise maximum of both vectors
     pub fn lcs(&self, other: &Self) -> Self {
+        let mut out = self.clone();
+        for (u, v) in other.iter() {
+            if *v > out.get(u) {
+                out.set(u, *v)
+            }
+        }
+        out
+    }
+    /// Greatest common predecessor, component-wise minimum of both vectors
+    pub fn gcp(&self, other: &Self) -> Self {
         let mut out = StateVector::new();
-        for (u, v) in other.0.iter().enumerate() {
-            let this = self.get(u as SessionId);
-            out.add(u as SessionId, v + this)
+        for (u, v) in self.iter() {
+            out.set(u, (*v).min(other.get(u)))
         }
         out
     }
+    /// Per-session count of requests, which are seen by other, but not by this vector
+    pub fn diff(&self, other: &Self) -> Self {
+        let mut out = StateVector::new();
+        for (u, v) in other.iter() {
+            out.set(u, v.saturating_sub(self.get(u)))
+        }
+        out
+    }
+    /// Count of requests seen by this vector
+    pub fn total(&self) -> usize {
+        self.0.iter().map(|(_, v)| v).sum()
+    }
 }
 
-impl AddAssign for StateVector {
-    fn add_assign(&mut self, rhs: Self) {
-        let StateVector(rhs) = rhs;
-        for (u, v) in rhs.into_iter().enumerate() {
-            self.add(u as SessionId, v)
+/// Vectors are ordered by causality, concurrent vectors are not comparable
+impl PartialOrd for StateVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.casually_before(other), other.casually_before(self)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (false, false) => None,
+        }
+    }
+}
+
+/// Sparse `session:count` notation, components are separated by `;`, i.e `1:3;4:2`
+impl fmt::Display for StateVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (u, v) in self.iter() {
+            if !first {
+                write!(f, ";")?;
+            }
+            first = false;
+            write!(f, "{}:{}", u, v)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseError {
+    /// Component is not in `session:count` form
+    Malformed(String),
+    SessionOutOfRange(String),
+    InvalidCount(String),
+    DuplicateSession(SessionId),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed(c) => write!(f, "malformed component: {:?}", c),
+            ParseError::SessionOutOfRange(s) => write!(f, "session id out of range: {}", s),
+            ParseError::InvalidCount(c) => write!(f, "invalid request count: {:?}", c),
+            ParseError::DuplicateSession(s) => write!(f, "duplicate session: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for StateVector {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut vector = StateVector::new();
+        let mut seen = Vec::new();
+        if s.is_empty() {
+            return Ok(vector);
         }
+        for component in s.split(';') {
+            let mut parts = component.splitn(2, ':');
+            let (session, count) = match (parts.next(), parts.next()) {
+                (Some(session), Some(count)) => (session, count),
+                _ => return Err(ParseError::Malformed(component.to_owned())),
+            };
+            if session.is_empty() || !session.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::Malformed(component.to_owned()));
+            }
+            let session = session
+                .parse::<SessionId>()
+                .map_err(|_| ParseError::SessionOutOfRange(session.to_owned()))?;
+            let count = count
+                .parse::<usize>()
+                .map_err(|_| ParseError::InvalidCount(count.to_owned()))?;
+            if seen.contains(&session) {
+                return Err(ParseError::DuplicateSession(session));
+            }
+            seen.push(session);
+            vector.set(session, count);
+        }
+        Ok(vector)
     }
 }
 
-impl Default for StateVector {
-    fn default() -> Self {
-        Self(Vec::default())
+/// # Panics
+///
+/// If any component overflows, see [`StateVector::checked_add`]
+impl AddAssign for StateVector {
+    fn add_assign(&mut self, rhs: Self) {
+        for (u, v) in rhs.iter() {
+            self.add(u, *v)
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use super::StateVector;
+    use super::{ParseError, StateVector};
+    use proptest::prelude::*;
+    use std::cmp::Ordering;
+
+    fn vector() -> impl Strategy<Value = StateVector> {
+        prop::collection::vec((0u16..6, 0usize..4), 0..6).prop_map(|components| {
+            let mut vector = StateVector::new();
+            for (u, v) in components {
+                vector.set(u, v);
+            }
+            vector
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn order_matches_causality(a in vector(), b in vector()) {
+            prop_assert_eq!(a <= b, a.casually_before(&b));
+            prop_assert_eq!(a.partial_cmp(&b), b.partial_cmp(&a).map(Ordering::reverse));
+            prop_assert_eq!(a.partial_cmp(&b) == Some(Ordering::Equal), a == b);
+        }
+
+        #[test]
+        fn lcs_is_least_upper_bound(a in vector(), b in vector(), c in vector()) {
+            let lcs = a.lcs(&b);
+            prop_assert!(a <= lcs && b <= lcs);
+            prop_assert_eq!(&lcs, &b.lcs(&a));
+            if a <= c && b <= c {
+                prop_assert!(lcs <= c);
+            }
+        }
+
+        #[test]
+        fn gcp_is_greatest_lower_bound(a in vector(), b in vector(), c in vector()) {
+            let gcp = a.gcp(&b);
+            prop_assert!(gcp <= a && gcp <= b);
+            prop_assert_eq!(&gcp, &b.gcp(&a));
+            if c <= a && c <= b {
+                prop_assert!(c <= gcp);
+            }
+        }
+
+        #[test]
+        fn diff_completes_to_lcs(a in vector(), b in vector()) {
+            let mut completed = a.clone();
+            completed += a.diff(&b);
+            prop_assert_eq!(&completed, &a.lcs(&b));
+            prop_assert_eq!(a.total() + a.diff(&b).total(), a.lcs(&b).total());
+            prop_assert_eq!(a.diff(&b).total() == 0, b <= a);
+        }
+
+        #[test]
+        fn total_is_monotonic(a in vector(), b in vector()) {
+            if a < b {
+                prop_assert!(a.total() < b.total());
+            }
+            prop_assert_eq!(a.lcs(&b).total() + a.gcp(&b).total(), a.total() + b.total());
+        }
+    }
 
+    #[test]
     fn lcs() {
-        let mut state = StateVector::new();
-        state.add(1, 2);
-        state.add(2, 3);
+        let mut a = StateVector::new();
+        a.add(1, 2);
+        a.add(2, 3);
+        let mut b = StateVector::new();
+        b.add(1, 4);
+        b.add(3, 1);
+
+        let mut expected = StateVector::new();
+        expected.set(1, 4);
+        expected.set(2, 3);
+        expected.set(3, 1);
+        assert_eq!(a.lcs(&b), expected);
+    }
+
+    #[test]
+    fn notation() {
+        let mut vector = StateVector::new();
+        vector.set(4, 2);
+        vector.set(1, 3);
+        vector.set(2, 0);
+        assert_eq!(vector.to_string(), "1:3;4:2");
+        assert_eq!("4:2;1:3".parse::<StateVector>().unwrap(), vector);
+        assert_eq!("1:3;2:0;4:2".parse::<StateVector>().unwrap(), vector);
+        assert_eq!("".parse::<StateVector>().unwrap(), StateVector::new());
+        assert_eq!(StateVector::new().to_string(), "");
+    }
+
+    #[test]
+    fn notation_errors() {
+        assert_eq!(
+            "1:3;;2:1".parse::<StateVector>(),
+            Err(ParseError::Malformed("".to_owned()))
+        );
+        assert_eq!(
+            "1".parse::<StateVector>(),
+            Err(ParseError::Malformed("1".to_owned()))
+        );
+        assert_eq!(
+            "-1:2".parse::<StateVector>(),
+            Err(ParseError::Malformed("-1:2".to_owned()))
+        );
+        assert_eq!(
+            "65536:1".parse::<StateVector>(),
+            Err(ParseError::SessionOutOfRange("65536".to_owned()))
+        );
+        assert_eq!(
+            "1:x".parse::<StateVector>(),
+            Err(ParseError::InvalidCount("x".to_owned()))
+        );
+        assert_eq!(
+            "1:2;1:3".parse::<StateVector>(),
+            Err(ParseError::DuplicateSession(1))
+        );
+    }
+
+    #[test]
+    fn sparse_sessions() {
+        let mut vector = StateVector::new();
+        vector.add(0, 1);
+        vector.add(60000, 2);
+        vector.add(1, 3);
+        vector.add(60000, 1);
+        assert_eq!(vector.get(0), 1);
+        assert_eq!(vector.get(1), 3);
+        assert_eq!(vector.get(60000), 3);
+        assert_eq!(vector.get(2), 0);
+        assert_eq!(vector.sessions().collect::<Vec<_>>(), [0, 1, 60000]);
+        assert_eq!(vector.to_string(), "0:1;1:3;60000:3");
+    }
+
+    #[test]
+    fn checked_remove() {
+        let mut vector = StateVector::new();
+        vector.add(3, 2);
+        assert_eq!(vector.checked_remove(3, 3), None);
+        assert_eq!(vector.get(3), 2);
+        assert_eq!(vector.checked_remove(4, 1), None);
+        assert_eq!(vector.checked_remove(3, 2), Some(0));
+        assert_eq!(vector, StateVector::new());
+    }
+
+    #[test]
+    fn checked_add() {
+        let mut vector: StateVector = format!("3:{}", usize::MAX - 1).parse().unwrap();
+        assert_eq!(vector.checked_add(3, 2), None);
+        assert_eq!(vector.get(3), usize::MAX - 1);
+        assert_eq!(vector.checked_add(3, 1), Some(usize::MAX));
+        assert_eq!(vector.checked_add(4, 0), Some(0));
+        assert_eq!(vector.to_string(), format!("3:{}", usize::MAX));
+    }
+
+    #[test]
+    fn trailing_zeros() {
+        let mut a = StateVector::new();
+        a.add(1, 2);
+        let mut b = a.clone();
+        b.set(4, 0);
+        assert_eq!(a, b);
     }
 }